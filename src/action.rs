@@ -1,8 +1,16 @@
-#[derive(PartialEq)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum Action {
-    Show,
     Apply,
     UnApply,
     IsApplied,
-    Usage,
+}
+impl Action {
+    // unit scripts are named <name>-<suffix>
+    pub fn script_suffix(&self) -> &'static str {
+        match self {
+            Action::Apply => "apply",
+            Action::UnApply => "unapply",
+            Action::IsApplied => "is-applied",
+        }
+    }
 }
@@ -11,33 +11,60 @@ use std::{
 };
 
 use crate::{
-    cmd::{self, Args, Vars},
+    action::Action,
+    cmd::{self, Args, EnvOptions, Vars},
     dryrun::{self, execute, ActionResult},
+    passive::{self, Event},
 };
 
-pub(crate) fn execute_apply(
+// the apply or unapply script of a unit or an inline --then or --undo
+pub(crate) fn execute_script(
+    action: Action,
     script: &cmd::VirtualFile,
     vars: Vars,
+    env: &EnvOptions,
     mode: crate::files::Mode,
 ) -> Result<ActionResult, ApplyError> {
     let args = Args::new();
-    dryrun::execute(mode, script, args, &vars).map_err(|e| {
-        ApplyError::ExecError(format!(
-            "execute_apply execute failed: {:?} {:?} {:?} {:?}",
-            script, vars, mode, e
-        ))
+    dryrun::execute(mode, script, args, &vars, env).map_err(|e| {
+        error!(
+            "execute {} failed: {:?} {:?} {:?} {:?}",
+            action.script_suffix(),
+            script,
+            vars,
+            mode,
+            e
+        );
+        e
     })
 }
-pub(crate) fn is_applied(script: &cmd::VirtualFile, vars: HashMap<String, String>) -> bool {
+pub(crate) fn is_applied(
+    script: &cmd::VirtualFile,
+    vars: HashMap<String, String>,
+    env: &EnvOptions,
+) -> Result<bool, ApplyError> {
     let args = Args::new();
-    match execute(crate::files::Mode::Active, script, args, &vars) {
+    // only a non zero exit is Unapplied, a check that can't run is an error
+    let applied = match execute(crate::files::Mode::Active, script, args, &vars, env) {
         Ok(_) => {
-            println!("{}", Green.paint("Applied"));
+            passive::text(Green.paint("Applied"));
             true
         }
-        Err(_e) => {
-            println!("{}", Yellow.paint("Unapplied"));
+        Err(ApplyError::NotZeroExit(_)) => {
+            passive::text(Yellow.paint("Unapplied"));
             false
         }
-    }
+        Err(e) => return Err(e),
+    };
+    passive::emit(Event {
+        action: String::from("is-applied"),
+        script: Some(script.to_string()),
+        result: Some(if applied {
+            ActionResult::AlreadyApplied
+        } else {
+            ActionResult::NotApplied
+        }),
+        ..Event::default()
+    });
+    Ok(applied)
 }
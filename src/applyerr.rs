@@ -36,6 +36,9 @@ pub enum ApplyError {
     #[error("Variable not found {0}")]
     VarNotFound(String),
 
+    #[error("Template error {0}")]
+    TemplateError(String),
+
     #[error("Name not found {0}")]
     NameNotFound(String),
 
@@ -78,6 +81,12 @@ pub enum ApplyError {
     #[error("Script Error {0}")]
     ScriptError(String),
 
+    #[error("{0} steps failed")]
+    StepsFailed(usize),
+
+    #[error("Dependency cycle between {0}")]
+    DependencyCycle(String),
+
     #[error("Io Error {0}")]
     IoError(#[from] std::io::Error),
 
@@ -91,6 +100,24 @@ pub enum ApplyError {
     AnyHowError(#[from] anyhow::Error),
 }
 
+// see "Exit codes" in README.md
+impl ApplyError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ApplyError::NotZeroExit(_) | ApplyError::CmdExitedPrematurely => 10,
+            ApplyError::InsufficientPrivileges(_) => 11,
+            ApplyError::VarNotFound(_) | ApplyError::TemplateError(_) => 12,
+            ApplyError::NameNotFound(_)
+            | ApplyError::PathNotFound(_)
+            | ApplyError::PathBufNotFound(_)
+            | ApplyError::PathNotFound0
+            | ApplyError::CommandNotFound(_) => 13,
+            ApplyError::StepsFailed(_) | ApplyError::DependencyCycle(_) => 14,
+            _ => 255,
+        }
+    }
+}
+
 impl From<ConfigError> for ApplyError {
     fn from(error: ConfigError) -> Self {
         ApplyError::ConfigError(error)
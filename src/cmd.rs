@@ -1,5 +1,8 @@
 use crate::applyerr::ApplyError;
+use crate::configfile;
 use crate::fs;
+use crate::passive;
+use crate::resolver;
 use ansi_term::Colour::{Green, Red, Yellow};
 use anyhow::{Context, Result};
 use env_logger::Env;
@@ -21,6 +24,75 @@ pub type Vars = HashMap<String, String>;
 
 pub type Args = Vec<String>;
 
+// how Vars are exported to the environment of scripts and commands
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct EnvOptions {
+    /// prepend to each var name, e.g. FASTIDIOUS_
+    #[arg(long)]
+    pub env_prefix: Option<String>,
+    /// start from an empty environment instead of inheriting
+    #[arg(long)]
+    pub clean_env: bool,
+    /// with --clean-env, pass this variable through from the parent
+    #[arg(long, num_args=0..)]
+    pub pass_env: Vec<String>,
+}
+impl EnvOptions {
+    // variables to set on the child, sorted by name
+    pub fn to_env(&self, vars: &Vars) -> Vec<(String, String)> {
+        let prefix = self.env_prefix.clone().unwrap_or_default();
+        let mut env: Vec<(String, String)> = Vec::new();
+        if self.clean_env {
+            for name in &self.pass_env {
+                if let Ok(value) = env::var(name) {
+                    env.push((name.clone(), value));
+                }
+            }
+        }
+        for (k, v) in vars {
+            env.push((format!("{}{}", prefix, k), v.clone()));
+        }
+        env.sort();
+        env
+    }
+    // to_env with references looked up, see resolver.rs; a var the script does not
+    // mention is left out unless the script is a program that can't be read
+    pub fn resolved_env(
+        &self,
+        vars: &Vars,
+        script: &VirtualFile,
+    ) -> Result<Vec<(String, String)>, ApplyError> {
+        let prefix = self.env_prefix.clone().unwrap_or_default();
+        let text = script.text();
+        let names: Vec<String> = vars
+            .keys()
+            .filter(|k| {
+                text.as_ref()
+                    .is_none_or(|t| t.contains(&format!("{}{}", prefix, k)))
+            })
+            .cloned()
+            .collect();
+        Ok(self.to_env(&resolver::resolve_vars(vars, &names)?))
+    }
+    // env(1) style prefix for printing a command line
+    pub fn display(&self, vars: &Vars) -> String {
+        let shown: Vars = vars
+            .iter()
+            .map(|(k, v)| (k.clone(), resolver::display(v).to_owned()))
+            .collect();
+        let assignments: Vec<String> = self
+            .to_env(&shown)
+            .iter()
+            .map(|(k, v)| format!("{}={:?}", k, v))
+            .collect();
+        match (self.clean_env, assignments.is_empty()) {
+            (true, _) => format!("env -i {}", assignments.join(" ")),
+            (false, true) => String::new(),
+            (false, false) => format!("env {}", assignments.join(" ")),
+        }
+    }
+}
+
 pub(crate) fn _to_vars_split_eq(v: Vec<String>) -> Vars {
     v.iter()
         .filter_map(|s| s.split_once('='))
@@ -46,7 +118,7 @@ pub(crate) fn to_vars_split_odd(v: Vec<String>) -> Vars {
 }
 
 #[test]
-fn test_vars() -> () {
+fn test_vars() {
     {
         let v = vec![
             "a=1".to_string(),
@@ -72,6 +144,24 @@ fn test_vars() -> () {
     }
 }
 #[test]
+fn test_env_options() {
+    let mut vars = Vars::new();
+    vars.insert("B".into(), "2".into());
+    vars.insert("A".into(), "1".into());
+    let env = EnvOptions::default();
+    assert_eq!(
+        env.to_env(&vars),
+        vec![("A".into(), "1".into()), ("B".into(), "2".into())]
+    );
+    let env = EnvOptions {
+        env_prefix: Some("X_".into()),
+        clean_env: true,
+        pass_env: vec!["PATH".into()],
+    };
+    let names: Vec<String> = env.to_env(&vars).into_iter().map(|(k, _v)| k).collect();
+    assert_eq!(names, vec!["PATH", "X_A", "X_B"]);
+}
+#[test]
 fn test_virtual_file() -> Result<(), ApplyError> {
     let _ = env_logger::Builder::from_env(Env::default().default_filter_or("trace")).try_init();
     let text = String::from("Hello");
@@ -83,7 +173,7 @@ fn test_virtual_file() -> Result<(), ApplyError> {
     let n = o
         .file()
         .read_to_string(&mut s)
-        .with_context(|| format!("read_to_string"))?;
+        .with_context(|| "read_to_string")?;
     debug!("src contains: size: {} file contents {}", n, s);
     assert_eq!(s, text);
     Ok(())
@@ -110,7 +200,7 @@ impl Drop for ExecutableFile{
             debug!("delete {:?}", self.path);
             match fs::clean_tmp(&self.path) {
                 Ok(_) => (),
-                Err(e) => println!("delete failed (ignoring) {:?}",e ),
+                Err(e) => passive::text(format!("delete failed (ignoring) {:?}", e)),
             }
         }
     }
@@ -146,7 +236,7 @@ impl VirtualFile {
                         .create(true)
                         .truncate(true),
                     path.clone(),
-                    Some("#!/bin/sh"),
+                    Some(&format!("#!{}", configfile::settings().shell)),
                     source,
                 )?;
                 let fullpath = canonicalize(path).unwrap();
@@ -154,6 +244,13 @@ impl VirtualFile {
             }
         }
     }
+    // the script text, None for a program that is not text
+    pub fn text(&self) -> Option<String> {
+        match self {
+            VirtualFile::FsPath(p) => std::fs::read_to_string(p).ok(),
+            VirtualFile::InMemory(source) => Some(source.clone()),
+        }
+    }
     pub fn as_readable(&self) -> Result<ReadableFile, ApplyError> {
         match self {
             VirtualFile::FsPath(p) => {
@@ -1,6 +1,253 @@
-use config::builder::{BuilderState, ConfigBuilder, DefaultState};
 use config::{Config, ConfigError};
-use std::{collections::HashMap, path::PathBuf};
+use files::Mode;
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::OnceLock;
 
 use crate::applyerr::ApplyError;
+use crate::cmd::Vars;
+use crate::varsfile;
 
+#[test]
+fn test_settings() -> Result<(), ApplyError> {
+    let mut settings = Settings::default();
+    let conf = Config::builder()
+        .add_source(config::File::from_str(
+            "active = true\nshell = \"/bin/bash\"\ndelims = \"{{ }}\"\n[vars]\nuser = \"sean\"\n",
+            config::FileFormat::Toml,
+        ))
+        .build()?;
+    settings.merge("test", &conf)?;
+    assert!(matches!(settings.mode, Mode::Active));
+    assert_eq!(settings.shell, "/bin/bash");
+    assert_eq!(settings.delims, ("{{".to_owned(), "}}".to_owned()));
+    assert_eq!(settings.vars.get("user").unwrap(), "sean");
+    assert_eq!(settings.source("shell"), "test");
+    assert_eq!(settings.source("color"), "default");
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Color {
+    /// when stdout is a terminal and NO_COLOR is not set
+    Auto,
+    Always,
+    Never,
+}
+
+// lowest to highest: defaults, /etc/fastidious/config.*, <config dir>/fastidious/config.*,
+// ./fastidious.*, FASTIDIOUS_* environment, command line flags
+#[derive(Debug, Clone)]
+pub struct Settings {
+    // when none of --active, --passive or --interactive is given
+    pub mode: Mode,
+    // below vars files and --var
+    pub vars: Vars,
+    pub delims: (String, String),
+    // for a manifest without script_dir
+    pub script_dir: PathBuf,
+    // interpreter for inline scripts
+    pub shell: String,
+    pub color: Color,
+    // for age: vars and save --encrypt
+    pub age_identity: PathBuf,
+    // for save --encrypt and vars
+    pub vars_store: PathBuf,
+    // key -> where its value came from, for config show
+    sources: BTreeMap<String, String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Passive,
+            vars: Vars::new(),
+            delims: (String::from("@@"), String::from("@@")),
+            script_dir: PathBuf::from("."),
+            shell: String::from("/bin/sh"),
+            color: Color::Auto,
+            age_identity: dirs::config_dir()
+                .unwrap_or_default()
+                .join("fastidious")
+                .join("age.key"),
+            vars_store: dirs::config_dir()
+                .unwrap_or_default()
+                .join("fastidious")
+                .join("vars.yaml.age"),
+            sources: BTreeMap::new(),
+        }
+    }
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+pub fn set_settings(settings: Settings) {
+    let _ = SETTINGS.set(settings);
+}
+// the defaults until main has loaded them
+pub fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(Settings::default)
+}
+
+// the config files and environment, each a separate source so values can be traced
+fn layers() -> Result<Vec<(String, Config)>, ApplyError> {
+    let mut files: Vec<(String, PathBuf)> = vec![(
+        String::from("system"),
+        PathBuf::from("/etc/fastidious/config"),
+    )];
+    if let Some(dir) = dirs::config_dir() {
+        files.push((String::from("user"), dir.join("fastidious").join("config")));
+    }
+    files.push((String::from("project"), PathBuf::from("fastidious")));
+    let mut layers = Vec::new();
+    for (kind, path) in files {
+        let conf = Config::builder()
+            .add_source(config::File::with_name(&path.to_string_lossy()).required(false))
+            .build()?;
+        layers.push((format!("{} {}.*", kind, path.display()), conf));
+    }
+    // FASTIDIOUS_VARS__USER=sean sets vars.user
+    let env = Config::builder()
+        .add_source(
+            config::Environment::with_prefix("FASTIDIOUS")
+                .prefix_separator("_")
+                .separator("__"),
+        )
+        .build()?;
+    layers.push((String::from("env FASTIDIOUS_*"), env));
+    Ok(layers)
+}
+
+fn get<'de, T: serde::Deserialize<'de>>(conf: &Config, key: &str) -> Result<Option<T>, ApplyError> {
+    match conf.get::<T>(key) {
+        Ok(value) => Ok(Some(value)),
+        Err(ConfigError::NotFound(_)) => Ok(None),
+        Err(e) => Err(ApplyError::ConfigError(e)),
+    }
+}
+
+impl Settings {
+    pub fn load() -> Result<Self, ApplyError> {
+        let mut settings = Settings::default();
+        for (source, conf) in layers()? {
+            settings.merge(&source, &conf)?;
+        }
+        debug!("settings {:?}", settings);
+        Ok(settings)
+    }
+    fn merge(&mut self, source: &str, conf: &Config) -> Result<(), ApplyError> {
+        // active = true is the same as mode = "active"
+        if let Some(true) = get::<bool>(conf, "active")? {
+            self.set_mode(Mode::Active, source);
+        }
+        if let Some(mode) = get::<Mode>(conf, "mode")? {
+            self.set_mode(mode, source);
+        }
+        if let Ok(vars) = conf.get_table("vars") {
+            let mut flat = Vars::new();
+            for (key, value) in vars {
+                varsfile::flatten(key, value, &mut flat)?;
+            }
+            for (key, value) in flat {
+                self.sources
+                    .insert(format!("vars.{}", key), source.to_owned());
+                self.vars.insert(key, value);
+            }
+        }
+        if let Some(delims) = get::<String>(conf, "delims")? {
+            match delims.split_whitespace().collect::<Vec<&str>>()[..] {
+                [left, right] => self.set_delims(left, right, source),
+                _ => {
+                    return Err(ApplyError::Error(format!(
+                        "delims in {}: expected \"<left> <right>\"",
+                        source
+                    )))
+                }
+            }
+        }
+        if let Some(script_dir) = get::<PathBuf>(conf, "script_dir")? {
+            self.script_dir = script_dir;
+            self.sources
+                .insert(String::from("script_dir"), source.to_owned());
+        }
+        if let Some(shell) = get::<String>(conf, "shell")? {
+            self.shell = shell;
+            self.sources
+                .insert(String::from("shell"), source.to_owned());
+        }
+        if let Some(age_identity) = get::<PathBuf>(conf, "age_identity")? {
+            self.age_identity = age_identity;
+            self.sources
+                .insert(String::from("age_identity"), source.to_owned());
+        }
+        if let Some(vars_store) = get::<PathBuf>(conf, "vars_store")? {
+            self.vars_store = vars_store;
+            self.sources
+                .insert(String::from("vars_store"), source.to_owned());
+        }
+        if let Some(color) = get::<Color>(conf, "color")? {
+            self.set_color(color, source);
+        }
+        Ok(())
+    }
+    pub fn set_mode(&mut self, mode: Mode, source: &str) {
+        self.mode = mode;
+        self.sources.insert(String::from("mode"), source.to_owned());
+    }
+    pub fn set_delims(&mut self, left: &str, right: &str, source: &str) {
+        self.delims = (left.to_owned(), right.to_owned());
+        self.sources
+            .insert(String::from("delims"), source.to_owned());
+    }
+    pub fn set_color(&mut self, color: Color, source: &str) {
+        self.color = color;
+        self.sources
+            .insert(String::from("color"), source.to_owned());
+    }
+    pub fn source(&self, key: &str) -> &str {
+        self.sources.get(key).map_or("default", |s| s.as_str())
+    }
+    // key, value, source for config show
+    pub fn rows(&self) -> Vec<(String, String, String)> {
+        let mut rows: Vec<(String, String)> = vec![
+            (
+                String::from("mode"),
+                format!("{:?}", self.mode).to_lowercase(),
+            ),
+            (
+                String::from("delims"),
+                format!("{} {}", self.delims.0, self.delims.1),
+            ),
+            (
+                String::from("script_dir"),
+                self.script_dir.display().to_string(),
+            ),
+            (String::from("shell"), self.shell.clone()),
+            (
+                String::from("color"),
+                format!("{:?}", self.color).to_lowercase(),
+            ),
+            (
+                String::from("age_identity"),
+                self.age_identity.display().to_string(),
+            ),
+            (
+                String::from("vars_store"),
+                self.vars_store.display().to_string(),
+            ),
+        ];
+        let mut vars: Vec<(&String, &String)> = self.vars.iter().collect();
+        vars.sort();
+        for (key, value) in vars {
+            rows.push((format!("vars.{}", key), value.clone()));
+        }
+        rows.into_iter()
+            .map(|(key, value)| {
+                let source = self.source(&key).to_owned();
+                (key, value, source)
+            })
+            .collect()
+    }
+}
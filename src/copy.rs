@@ -0,0 +1,325 @@
+use applyerr::ApplyError;
+use diff::{ask_update, diff, vimdiff, DiffStatus, DiffText, Update};
+use dryrun::ActionResult;
+use file::{apply_changes, log_no_change, parse_mode, Change};
+use files::Mode;
+use fs::{can_write_file, file_hash, gid_of, ids, uid_of};
+use link::{ensure_link, LinkArgs};
+use passive::Verb::{self, Live, Skipped, Would};
+use passive::{self, color_from_verb, Event};
+use std::fs::{self, Metadata};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use userinput::ask;
+
+#[test]
+fn test_copy() -> Result<(), ApplyError> {
+    let dir = std::env::temp_dir().join("test_copy");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("src/sub"))?;
+    fs::write(dir.join("src/a.txt"), "a\n")?;
+    fs::write(dir.join("src/sub/b.txt"), "b\n")?;
+    // a link is copied as a link, not followed round the loop
+    std::os::unix::fs::symlink("..", dir.join("src/sub/up"))?;
+    let args = CopyArgs {
+        src: dir.join("src"),
+        dest: dir.join("dest"),
+        mode: Some(0o640),
+        ..CopyArgs::default()
+    };
+    assert!(matches!(copy(Mode::Passive, &args)?, ActionResult::Skipped));
+    assert!(!args.dest.exists());
+    assert!(matches!(copy(Mode::Active, &args)?, ActionResult::Applied));
+    assert_eq!(fs::read_to_string(dir.join("dest/sub/b.txt"))?, "b\n");
+    assert_eq!(fs::read_link(dir.join("dest/sub/up"))?, PathBuf::from(".."));
+    assert_eq!(fs::metadata(dir.join("dest/a.txt"))?.mode() & 0o7777, 0o640);
+    assert!(matches!(
+        copy(Mode::Active, &args)?,
+        ActionResult::AlreadyApplied
+    ));
+    fs::write(dir.join("dest/a.txt"), "changed\n")?;
+    assert!(matches!(copy(Mode::Active, &args)?, ActionResult::Applied));
+    assert_eq!(fs::read_to_string(dir.join("dest/a.txt"))?, "a\n");
+    fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct CopyArgs {
+    /// a file, or a directory copied with everything in it, links in it as links
+    #[arg(short, long)]
+    pub src: PathBuf,
+    #[arg(short, long)]
+    pub dest: PathBuf,
+    /// octal permissions of the copied files, the source's by default
+    #[arg(short, long, value_parser = parse_mode)]
+    pub mode: Option<u32>,
+    /// user name or uid, the source's by default when run as root
+    #[arg(long)]
+    pub owner: Option<String>,
+    /// group name or gid, the source's by default when run as root
+    #[arg(long)]
+    pub group: Option<String>,
+}
+
+pub fn copy(mode: Mode, args: &CopyArgs) -> Result<ActionResult, ApplyError> {
+    let meta =
+        fs::metadata(&args.src).map_err(|_| ApplyError::PathBufNotFound(args.src.clone()))?;
+    if !meta.is_dir() {
+        return copy_file(mode, &args.src, &args.dest, args);
+    }
+    let mut results = Vec::new();
+    copy_dir(mode, &args.src, &args.dest, args, &mut results)?;
+    Ok(
+        if results.iter().any(|r| matches!(r, ActionResult::Applied)) {
+            ActionResult::Applied
+        } else if results.iter().any(|r| matches!(r, ActionResult::Skipped)) {
+            ActionResult::Skipped
+        } else {
+            ActionResult::AlreadyApplied
+        },
+    )
+}
+// the directory, then what is in it in name order
+fn copy_dir(
+    mode: Mode,
+    src: &Path,
+    dest: &Path,
+    args: &CopyArgs,
+    results: &mut Vec<ActionResult>,
+) -> Result<(), ApplyError> {
+    let src_meta = fs::metadata(src)?;
+    let found = match fs::metadata(dest) {
+        Ok(meta) if meta.is_dir() => Some(Attrs::of(&meta)),
+        Ok(_) => return Err(ApplyError::Error(format!("{:?} is not a directory", dest))),
+        Err(_) => None,
+    };
+    if found.is_none() {
+        can_write_file(dest.to_path_buf())?;
+        results.push(apply_changes(mode, &[(dest.to_owned(), Change::Mkdir)])?);
+    }
+    let current = match found {
+        Some(current) => current,
+        None if dest.is_dir() => Attrs::of(&fs::metadata(dest)?),
+        None if matches!(mode, Mode::Passive) => Attrs::new_path(),
+        // nothing can be copied into a directory that was not made
+        None => return Ok(()),
+    };
+    let changes = attr_changes(dest, &src_meta, &current, None, args)?;
+    if !changes.is_empty() {
+        results.push(apply_changes(mode, &changes)?);
+    }
+    let mut entries: Vec<PathBuf> = fs::read_dir(src)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>()?;
+    entries.sort();
+    for entry in entries {
+        let name = entry.file_name().expect("a directory entry");
+        let file_type = entry.symlink_metadata()?.file_type();
+        if file_type.is_symlink() {
+            let link = LinkArgs {
+                target: fs::read_link(&entry)?,
+                ..LinkArgs::default()
+            };
+            results.push(ensure_link(mode, &dest.join(name), &link)?);
+        } else if file_type.is_dir() {
+            copy_dir(mode, &entry, &dest.join(name), args, results)?;
+        } else {
+            results.push(copy_file(mode, &entry, &dest.join(name), args)?);
+        }
+    }
+    Ok(())
+}
+
+// the contents by SHA-256, then mode and ownership
+pub fn copy_file(
+    mode: Mode,
+    src: &Path,
+    dest: &Path,
+    args: &CopyArgs,
+) -> Result<ActionResult, ApplyError> {
+    let src_meta = fs::metadata(src)?;
+    let dest_meta = match fs::metadata(dest) {
+        Ok(meta) if !meta.is_file() => return Err(ApplyError::NotAFile(dest.to_owned())),
+        Ok(meta) => Some(meta),
+        Err(_) => None,
+    };
+    let same = match &dest_meta {
+        Some(meta) => meta.len() == src_meta.len() && file_hash(src)? == file_hash(dest)?,
+        None => false,
+    };
+    let mut result = ActionResult::AlreadyApplied;
+    if !same {
+        can_write_file(dest.to_path_buf())?;
+        result = match mode {
+            Mode::Passive => {
+                log_copy_action(Would, src, dest, difftext(src, dest).as_ref());
+                ActionResult::Skipped
+            }
+            Mode::Active => write_copy(src, dest)?,
+            Mode::Interactive if dest_meta.is_none() => {
+                match ask(&format!(
+                    "copy {} to {} (y/n)",
+                    src.display(),
+                    dest.display()
+                )) {
+                    'y' => write_copy(src, dest)?,
+                    _ => {
+                        log_copy_action(Skipped, src, dest, None);
+                        ActionResult::Skipped
+                    }
+                }
+            }
+            Mode::Interactive => copy_interactive(src, dest)?,
+        };
+    }
+    // a copy gets the source's permission bits, a file that is not copied keeps its own
+    let current = match result {
+        ActionResult::Applied => Some(Attrs::of(&fs::metadata(dest)?)),
+        _ if !same && matches!(mode, Mode::Passive) => Some(Attrs {
+            mode: Some(src_meta.mode() & 0o7777),
+            ..dest_meta
+                .as_ref()
+                .map(Attrs::of)
+                .unwrap_or_else(Attrs::new_path)
+        }),
+        _ => dest_meta.as_ref().map(Attrs::of),
+    };
+    if let Some(current) = current {
+        let changes = attr_changes(dest, &src_meta, &current, args.mode, args)?;
+        if !changes.is_empty() {
+            match apply_changes(mode, &changes)? {
+                ActionResult::Applied => result = ActionResult::Applied,
+                ActionResult::Skipped if matches!(result, ActionResult::AlreadyApplied) => {
+                    result = ActionResult::Skipped
+                }
+                _ => (),
+            }
+        }
+    }
+    if matches!(result, ActionResult::AlreadyApplied) {
+        log_no_change("copy", dest);
+    } else {
+        passive::emit(Event {
+            action: String::from("copy"),
+            source: Some(src.display().to_string()),
+            dest: Some(dest.display().to_string()),
+            result: Some(result),
+            ..Event::default()
+        });
+    }
+    Ok(result)
+}
+// what a path has, or would have after the copy
+struct Attrs {
+    // None when not known yet, for a directory still to be made
+    mode: Option<u32>,
+    uid: u32,
+    gid: u32,
+}
+
+impl Attrs {
+    fn of(meta: &Metadata) -> Attrs {
+        Attrs {
+            mode: Some(meta.mode() & 0o7777),
+            uid: meta.uid(),
+            gid: meta.gid(),
+        }
+    }
+    // owned by whoever runs the copy
+    fn new_path() -> Attrs {
+        let (uid, gid) = ids();
+        Attrs {
+            mode: None,
+            uid,
+            gid,
+        }
+    }
+}
+
+// --mode, --owner and --group, or the source's mode and, as root, its owner and group
+fn attr_changes(
+    dest: &Path,
+    src_meta: &Metadata,
+    current: &Attrs,
+    mode: Option<u32>,
+    args: &CopyArgs,
+) -> Result<Vec<(PathBuf, Change)>, ApplyError> {
+    let root = ids().0 == 0;
+    let mut changes = Vec::new();
+    let mode = mode.unwrap_or(src_meta.mode() & 0o7777);
+    if current.mode != Some(mode) {
+        changes.push((dest.to_owned(), Change::Chmod(mode)));
+    }
+    let owner = match &args.owner {
+        Some(owner) => Some((uid_of(owner)?, owner.clone())),
+        None if root => Some((src_meta.uid(), src_meta.uid().to_string())),
+        None => None,
+    };
+    if let Some((uid, owner)) = owner {
+        if current.uid != uid {
+            changes.push((dest.to_owned(), Change::Chown(uid, owner)));
+        }
+    }
+    let group = match &args.group {
+        Some(group) => Some((gid_of(group)?, group.clone())),
+        None if root => Some((src_meta.gid(), src_meta.gid().to_string())),
+        None => None,
+    };
+    if let Some((gid, group)) = group {
+        if current.gid != gid {
+            changes.push((dest.to_owned(), Change::Chgrp(gid, group)));
+        }
+    }
+    Ok(changes)
+}
+fn copy_interactive(src: &Path, dest: &Path) -> Result<ActionResult, ApplyError> {
+    match ask_update(src.display(), dest.display(), false) {
+        Update::Overwrite => write_copy(src, dest),
+        Update::Merge => {
+            vimdiff(src, dest)?;
+            Ok(ActionResult::Applied)
+        }
+        Update::Diff => {
+            log_copy_action(Would, src, dest, difftext(src, dest).as_ref());
+            copy_interactive(src, dest)
+        }
+        Update::Skip | Update::MergeToSource => {
+            log_copy_action(Skipped, src, dest, None);
+            Ok(ActionResult::Skipped)
+        }
+    }
+}
+fn write_copy(src: &Path, dest: &Path) -> Result<ActionResult, ApplyError> {
+    log_copy_action(Live, src, dest, None);
+    fs::copy(src, dest)
+        .map_err(|e| ApplyError::CopyError(src.to_owned(), dest.to_owned(), e.to_string()))?;
+    Ok(ActionResult::Applied)
+}
+fn difftext(src: &Path, dest: &Path) -> Option<DiffText> {
+    match diff(src.to_owned(), dest.to_owned()) {
+        DiffStatus::Changed(difftext) => Some(difftext),
+        _ => None,
+    }
+}
+fn log_copy_action(verb: Verb, src: &Path, dest: &Path, difftext: Option<&DiffText>) {
+    let color = color_from_verb(verb);
+    passive::text(format!(
+        "{}: {}: {} ->{}",
+        color.paint(verb.to_string()),
+        color.paint("copy"),
+        color.paint(src.display().to_string()),
+        color.paint(dest.display().to_string())
+    ));
+    if let Some(difftext) = difftext {
+        passive::text(difftext.colored());
+    }
+    passive::emit(Event {
+        verb: Some(verb),
+        action: String::from("copy"),
+        source: Some(src.display().to_string()),
+        dest: Some(dest.display().to_string()),
+        diff: difftext.map(|d| d.to_string()),
+        ..Event::default()
+    });
+}
@@ -1,52 +1,125 @@
 use crate::fs::{can_create_dir, can_create_parent_dir, create_parent_dir};
 use crate::passive::Verb;
 use ansi_term::Colour;
-use ansi_term::Colour::{Red, Yellow};
+use ansi_term::Colour::{Green, Red, Yellow};
 use applyerr::ApplyError;
 use cmd::exectable_full_path;
+use dryrun::ActionResult;
 use files::Mode;
 use files::{DestFile, GenFile, SrcFile};
-use fs::can_write_file;
+use fs::{can_write_file, file_hash};
+use linediff::unified_diff;
 use log::debug;
 use log::trace;
 use passive::color_from_verb;
 use passive::Verb::{Live, Skipped, Would};
+use passive::{self, Event};
 use std::fmt;
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::os::unix::fs::FileTypeExt;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
 use std::process::ExitStatus;
+use std::sync::OnceLock;
 use std::vec::IntoIter;
 use userinput::ask;
 
+#[test]
+fn test_diff() -> Result<(), ApplyError> {
+    let dir = std::env::temp_dir();
+    let gen = dir.join("test_diff.gen.tmp");
+    let dest = dir.join("test_diff.dest.tmp");
+    std::fs::write(&gen, "a\nb\n")?;
+    std::fs::write(&dest, "a\nb\n")?;
+    assert!(matches!(
+        diff(gen.clone(), dest.clone()),
+        DiffStatus::NoChanges
+    ));
+    std::fs::write(&dest, "a\nc\n")?;
+    assert!(matches!(
+        diff(gen.clone(), dest.clone()),
+        DiffStatus::Changed(DiffText::Text(_))
+    ));
+    std::fs::write(&dest, b"a\0b\n")?;
+    assert!(matches!(
+        diff(gen.clone(), dest.clone()),
+        DiffStatus::Changed(DiffText::Summary(_))
+    ));
+    assert!(matches!(
+        diff(gen.clone(), "/dev/null".into()),
+        DiffStatus::Device
+    ));
+    assert!(matches!(
+        diff(gen.clone(), dir.clone()),
+        DiffStatus::Unsupported
+    ));
+    std::fs::remove_file(&dest)?;
+    assert!(matches!(diff(gen.clone(), dest), DiffStatus::NewFile));
+    std::fs::remove_file(&gen)?;
+    Ok(())
+}
+
 #[derive(Debug)]
 pub enum DiffText {
-    Text(Vec<u8>),
-    Unsupported,
+    Text(String),
+    // binary or large files, only which files differ
+    Summary(String),
 }
 
+static CONTEXT: OnceLock<usize> = OnceLock::new();
+
+// lines of context around each hunk, 3 unless set by --diff-context
+pub fn set_context(context: usize) {
+    let _ = CONTEXT.set(context);
+}
+
+// larger files are compared by size and hash, not diffed line by line
+const MAX_DIFF_SIZE: u64 = 1024 * 1024;
+
 #[derive(Debug)]
 pub enum DiffStatus {
     NoChanges,
     NewFile,
     Changed(DiffText),
+    // char or block device or fifo like /dev/stdout, written without comparing
+    Device,
+    // a directory or socket
     Unsupported,
     Failed,
 }
+impl DiffText {
+    // for the terminal, Display is plain text for json
+    pub fn colored(&self) -> String {
+        match self {
+            DiffText::Text(difftext) => difftext
+                .lines()
+                .map(|line| {
+                    if line.starts_with("+++") || line.starts_with("---") {
+                        Colour::White.bold().paint(line).to_string()
+                    } else if line.starts_with('+') {
+                        Green.paint(line).to_string()
+                    } else if line.starts_with('-') {
+                        Red.paint(line).to_string()
+                    } else if line.starts_with("@@") {
+                        Colour::Cyan.paint(line).to_string()
+                    } else {
+                        line.to_owned()
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+            DiffText::Summary(summary) => Yellow.paint(summary).to_string(),
+        }
+    }
+}
 impl fmt::Display for DiffText {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            DiffText::Text(difftext) => {
-                for u in difftext.iter() {
-                    let ch = *u as char;
-                    write!(f, "{}", ch)?;
-                }
-            }
-            DiffText::Unsupported => {
-                write!(f, "Unsupported")?;
-            }
+            DiffText::Text(difftext) => write!(f, "{}", difftext),
+            DiffText::Summary(summary) => write!(f, "{}", summary),
         }
-        Ok(())
     }
 }
 
@@ -56,57 +129,122 @@ pub fn log_template_action(
     template: &SrcFile,
     gen: &GenFile,
     dest: &DestFile,
+    difftext: Option<&DiffText>,
 ) {
     let color: Colour = color_from_verb(verb);
-    println!(
+    passive::text(format!(
         "{}: {} {} [{}]  ->{}",
         color.paint(verb.to_string()),
         color.paint(action),
         color.paint(template.to_string()),
         color.paint(gen.to_string()),
         color.paint(dest.to_string())
-    );
+    ));
+    if let Some(difftext) = difftext {
+        passive::text(difftext.colored());
+    }
+    passive::emit(Event {
+        verb: Some(verb),
+        action: action.to_owned(),
+        template: Some(template.to_string()),
+        dest: Some(dest.to_string()),
+        diff: difftext.map(|d| d.to_string()),
+        ..Event::default()
+    });
 }
 
+// path is the generated file, path2 the current dest; the diff is dest -> generated
 pub fn diff(path: PathBuf, path2: PathBuf) -> DiffStatus {
-    if !path2.exists() {
-        DiffStatus::NewFile
-    } else if !path2.is_file() {
+    // metadata follows symlinks, a link to a file is compared with its target
+    let dest_meta = match std::fs::metadata(&path2) {
+        Ok(meta) => meta,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => {
+            if path2.symlink_metadata().is_ok() {
+                debug!("dangling symlink {:?}, the target is created", path2);
+            }
+            return DiffStatus::NewFile;
+        }
+        Err(e) => {
+            debug!("diff metadata failed {:?}", e);
+            return DiffStatus::Failed;
+        }
+    };
+    let file_type = dest_meta.file_type();
+    if file_type.is_char_device() || file_type.is_block_device() || file_type.is_fifo() {
+        DiffStatus::Device
+    } else if !file_type.is_file() {
         DiffStatus::Unsupported
     } else {
-        let output = Command::new("diff")
-            .arg(path)
-            .arg(path2)
-            .output()
-            .expect("diff failed");
-        //io::stdout().write_all(&output.stdout).unwrap();
-        match output.status.code().unwrap() {
-            1 => DiffStatus::Changed(DiffText::Text(output.stdout)),
-            2 => DiffStatus::Failed,
-            0 => DiffStatus::NoChanges,
-            _ => DiffStatus::Failed,
+        match diff_files(&path, &path2, dest_meta.len()) {
+            Ok(status) => status,
+            Err(e) => {
+                debug!("diff read failed {:?}", e);
+                DiffStatus::Failed
+            }
         }
     }
 }
+// sizes first, then hashes for equal sizes, then a line diff unless binary or large;
+// not mtimes, the generated file is always newer than dest
+fn diff_files(path: &Path, path2: &Path, dest_len: u64) -> Result<DiffStatus, ApplyError> {
+    let gen_len = std::fs::metadata(path)?.len();
+    let large = gen_len.max(dest_len) > MAX_DIFF_SIZE;
+    if gen_len == dest_len && file_hash(path)? == file_hash(path2)? {
+        return Ok(DiffStatus::NoChanges);
+    }
+    let summary = |kind: &str| {
+        DiffStatus::Changed(DiffText::Summary(format!(
+            "{} {} and {} differ ({} -> {} bytes)",
+            kind,
+            path2.display(),
+            path.display(),
+            dest_len,
+            gen_len
+        )))
+    };
+    if large {
+        return Ok(summary("Large files"));
+    }
+    let current = std::fs::read(path2)?;
+    let generated = std::fs::read(path)?;
+    match (text(&current), text(&generated)) {
+        (Some(current), Some(generated)) => match unified_diff(
+            current,
+            generated,
+            &path2.display().to_string(),
+            &path.display().to_string(),
+            *CONTEXT.get().unwrap_or(&3),
+        ) {
+            Some(difftext) => Ok(DiffStatus::Changed(DiffText::Text(difftext))),
+            // too many changed lines to diff
+            None => Ok(summary("Files")),
+        },
+        _ => Ok(summary("Binary files")),
+    }
+}
+// utf8 without NUL bytes, the same check as git in the first 8000 bytes
+fn text(bytes: &[u8]) -> Option<&str> {
+    if bytes.iter().take(8000).any(|&b| b == 0) {
+        None
+    } else {
+        std::str::from_utf8(bytes).ok()
+    }
+}
 pub fn create_or_diff(
     mode: Mode,
     template: &SrcFile,
     dest: &DestFile,
     gen: &GenFile,
-) -> Result<DiffStatus, ApplyError> {
+) -> Result<ActionResult, ApplyError> {
     debug!("create_or_diff: diff {:?} {:?}", gen, dest.path());
-    diff(gen.path(), dest.path());
-    match update_from_template(mode, template, gen, dest) {
-        Ok(_) => Ok(diff(gen.path(), dest.path())),
-        Err(e) => Err(e),
-    }
+    update_from_template(mode, template, gen, dest)
 }
 pub fn update_from_template<'f>(
     mode: Mode,
     template: &'f SrcFile,
     gen: &'f GenFile,
     dest: &'f DestFile,
-) -> Result<(), ApplyError> {
+) -> Result<ActionResult, ApplyError> {
     trace!("update_from_template");
     trace!("dest {:?}", dest);
 
@@ -115,12 +253,19 @@ pub fn update_from_template<'f>(
     let status = diff(gen.path(), dest.path());
     match status {
         DiffStatus::NoChanges => {
-            println!(
+            passive::text(format!(
                 "{} {}",
                 Yellow.paint("NO CHANGE: "),
                 Yellow.paint(dest.to_string())
-            );
-            Ok(())
+            ));
+            passive::emit(Event {
+                action: String::from("create from template"),
+                template: Some(template.to_string()),
+                dest: Some(dest.to_string()),
+                result: Some(ActionResult::AlreadyApplied),
+                ..Event::default()
+            });
+            Ok(ActionResult::AlreadyApplied)
         }
         DiffStatus::Failed => {
             debug!("diff failed '{}'", dest);
@@ -135,14 +280,20 @@ pub fn update_from_template<'f>(
                 Mode::Interactive => copy_interactive(gen, dest, template),
             }
         }
-        DiffStatus::Unsupported => match mode {
+        DiffStatus::Unsupported => Err(ApplyError::NotAFile(dest.path())),
+        DiffStatus::Device => match mode {
             Mode::Passive => {
-                update_from_template_passive(DiffText::Unsupported, template, gen, dest)
-            }
-            Mode::Active => update_from_template_active(template, gen, dest),
-            Mode::Interactive => {
-                update_from_template_interactive(DiffText::Unsupported, template, gen, dest)
+                log_template_action("write to device", Would, template, gen, dest, None);
+                Ok(ActionResult::Skipped)
             }
+            Mode::Active => write_device(gen, dest, template),
+            Mode::Interactive => match ask(&format!("write {} to {} (y/n)", gen, dest)) {
+                'y' => write_device(gen, dest, template),
+                _ => {
+                    log_template_action("write to device", Skipped, template, gen, dest, None);
+                    Ok(ActionResult::Skipped)
+                }
+            },
         },
         DiffStatus::Changed(difftext) => match mode {
             Mode::Passive => update_from_template_passive(difftext, template, gen, dest),
@@ -151,24 +302,50 @@ pub fn update_from_template<'f>(
         },
     }
 }
-fn create_passive(gen: &GenFile, dest: &DestFile, template: &SrcFile) -> Result<(), ApplyError> {
+fn create_passive(
+    gen: &GenFile,
+    dest: &DestFile,
+    template: &SrcFile,
+) -> Result<ActionResult, ApplyError> {
     info!("template {:?}", template);
     can_create_parent_dir(dest.path())?;
-    can_create_parent_dir(gen.path())
+    can_create_parent_dir(gen.path())?;
+    log_template_action("create from template", Would, template, gen, dest, None);
+    Ok(ActionResult::Skipped)
 }
-fn copy_active(gen: &GenFile, dest: &DestFile, template: &SrcFile) -> Result<(), ApplyError> {
+fn copy_active(
+    gen: &GenFile,
+    dest: &DestFile,
+    template: &SrcFile,
+) -> Result<ActionResult, ApplyError> {
     create_parent_dir(Mode::Active, dest.path())?;
-    log_template_action("create from template", Live, template, gen, dest);
+    log_template_action("create from template", Live, template, gen, dest, None);
     match std::fs::copy(gen.path(), dest.path()) {
         Err(e) => Err(ApplyError::CopyError(
             gen.path(),
             dest.path(),
             e.to_string(),
         )),
-        Ok(_) => Ok(()),
+        Ok(_) => Ok(ActionResult::Applied),
     }
 }
-fn copy_interactive(gen: &GenFile, dest: &DestFile, _template: &SrcFile) -> Result<(), ApplyError> {
+// no create or truncate, a device is just written to
+fn write_device(
+    gen: &GenFile,
+    dest: &DestFile,
+    template: &SrcFile,
+) -> Result<ActionResult, ApplyError> {
+    log_template_action("write to device", Live, template, gen, dest, None);
+    let mut src = std::fs::File::open(gen.path())?;
+    let mut device = OpenOptions::new().write(true).open(dest.path())?;
+    std::io::copy(&mut src, &mut device)?;
+    Ok(ActionResult::Applied)
+}
+fn copy_interactive(
+    gen: &GenFile,
+    dest: &DestFile,
+    _template: &SrcFile,
+) -> Result<ActionResult, ApplyError> {
     // TODO: add vimdiff support
     // TODO: use ask and copy_passive
     let status = Command::new("cp")
@@ -178,9 +355,9 @@ fn copy_interactive(gen: &GenFile, dest: &DestFile, _template: &SrcFile) -> Resu
         .status()
         .expect("failed to execute process");
 
-    println!("with: {}", status);
+    passive::text(format!("with: {}", status));
     if status.success() {
-        Ok(())
+        Ok(ActionResult::Applied)
     } else {
         panic!("cp failed: {:?} -> {:?}", gen, dest)
     }
@@ -198,7 +375,7 @@ fn merge_into_template(
         .status()
         .expect("failed to execute process");
 
-    println!("with: {}", status);
+    passive::text(format!("with: {}", status));
     Ok(status.success())
 }
 fn exit_status_to_dryrun_error(r: std::io::Result<ExitStatus>) -> Result<(), ApplyError> {
@@ -215,10 +392,13 @@ fn merge_to_template_interactive(
     gen: &GenFile,
     dest: &DestFile,
 ) -> Result<(), ApplyError> {
+    vimdiff(&gen.path(), &dest.path())
+}
+pub fn vimdiff(left: &Path, right: &Path) -> Result<(), ApplyError> {
     let exe = String::from("vim");
     let real_exe: PathBuf = exectable_full_path(&exe)?;
     let mut cmd1 = Command::new(real_exe);
-    let cmd = cmd1.arg("-d").arg(gen).arg(dest);
+    let cmd = cmd1.arg("-d").arg(left).arg(right);
     exit_status_to_dryrun_error(cmd.status())
 }
 
@@ -227,16 +407,22 @@ fn update_from_template_passive(
     template: &SrcFile,
     gen: &GenFile,
     dest: &DestFile,
-) -> Result<(), ApplyError> {
-    log_template_action("create from template", Would, template, gen, dest);
-    println!("{}", difftext);
-    Ok(())
+) -> Result<ActionResult, ApplyError> {
+    log_template_action(
+        "create from template",
+        Would,
+        template,
+        gen,
+        dest,
+        Some(&difftext),
+    );
+    Ok(ActionResult::Skipped)
 }
 fn update_from_template_active(
     template: &SrcFile,
     gen: &GenFile,
     dest: &DestFile,
-) -> Result<(), ApplyError> {
+) -> Result<ActionResult, ApplyError> {
     copy_active(gen, dest, template)
 }
 fn update_from_template_interactive(
@@ -244,23 +430,60 @@ fn update_from_template_interactive(
     template: &SrcFile,
     gen: &GenFile,
     dest: &DestFile,
-) -> Result<(), ApplyError> {
-    let ans = ask(&format!(
-        "{}: {} {} (o)verwrite / (m)erge[vimdiff] / s(k)ip / (d)iff / merge to (t)emplate",
-        "files don't match", gen, dest
-    ));
-    match ans {
-        'd' => update_from_template_passive(difftext, template, gen, dest),
-        'k' => {
-            log_template_action("create from template", Skipped, template, gen, dest);
-            Ok(())
+) -> Result<ActionResult, ApplyError> {
+    match ask_update(gen, dest, true) {
+        Update::Diff => {
+            log_template_action(
+                "create from template",
+                Would,
+                template,
+                gen,
+                dest,
+                Some(&difftext),
+            );
+            update_from_template_interactive(difftext, template, gen, dest)
         }
-        't' => {
+        Update::Skip => {
+            log_template_action("create from template", Skipped, template, gen, dest, None);
+            Ok(ActionResult::Skipped)
+        }
+        Update::MergeToSource => {
+            // dest is left as is
             merge_into_template(template, gen, dest)?;
-            Ok(())
+            Ok(ActionResult::Skipped)
+        }
+        Update::Merge => merge_to_template_interactive(template, gen, dest)
+            .map(|_status_code| ActionResult::Applied),
+        Update::Overwrite => copy_active(gen, dest, template),
+    }
+}
+// what to do with a dest that differs from what it should be
+pub enum Update {
+    Overwrite,
+    Merge,
+    Skip,
+    Diff,
+    MergeToSource,
+}
+// merge to (t)emplate is only offered when there is a template to merge to
+pub fn ask_update<S: fmt::Display, D: fmt::Display>(src: S, dest: D, template: bool) -> Update {
+    let to_template = if template {
+        " / merge to (t)emplate"
+    } else {
+        ""
+    };
+    loop {
+        let ans = ask(&format!(
+            "{}: {} {} (o)verwrite / (m)erge[vimdiff] / s(k)ip / (d)iff{}",
+            "files don't match", src, dest, to_template
+        ));
+        match ans {
+            'o' => return Update::Overwrite,
+            'm' => return Update::Merge,
+            'k' => return Update::Skip,
+            'd' => return Update::Diff,
+            't' if template => return Update::MergeToSource,
+            _ => continue,
         }
-        'm' => merge_to_template_interactive(template, gen, dest).map(|_status_code| ()),
-        'o' => copy_active(gen, dest, template),
-        _ => update_from_template(Mode::Interactive, template, gen, dest),
     }
 }
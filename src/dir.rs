@@ -0,0 +1,149 @@
+use applyerr::ApplyError;
+use dryrun::ActionResult;
+use file::{apply_changes, log_no_change, parse_mode, Change};
+use files::Mode;
+use fs::{can_write_dir, can_write_to_parent_dir, gid_of, missing_dirs, uid_of};
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+#[test]
+fn test_ensure_dir() -> Result<(), ApplyError> {
+    let top = std::env::temp_dir().join("test_ensure_dir");
+    let _ = fs::remove_dir_all(&top);
+    let leaf = top.join("a/b");
+    assert_eq!(
+        missing_dirs(&leaf),
+        vec![top.clone(), top.join("a"), leaf.clone()]
+    );
+    let args = DirArgs {
+        mode: vec![0o750, 0o700],
+        purge: true,
+        keep: vec![String::from("kept")],
+        ..DirArgs::default()
+    };
+    assert!(matches!(
+        ensure_dir(Mode::Passive, &leaf, &args)?,
+        ActionResult::Skipped
+    ));
+    assert!(!top.exists());
+    assert!(matches!(
+        ensure_dir(Mode::Active, &leaf, &args)?,
+        ActionResult::Applied
+    ));
+    let mode = |p: &Path| fs::metadata(p).map(|m| m.permissions().mode() & 0o7777);
+    assert_eq!(mode(&top.join("a"))?, 0o750);
+    assert_eq!(mode(&leaf)?, 0o700);
+    fs::write(leaf.join("kept"), "")?;
+    fs::write(leaf.join("stray"), "")?;
+    assert!(matches!(
+        ensure_dir(Mode::Active, &leaf, &args)?,
+        ActionResult::Applied
+    ));
+    assert!(leaf.join("kept").exists() && !leaf.join("stray").exists());
+    assert!(matches!(
+        ensure_dir(Mode::Active, &leaf, &args)?,
+        ActionResult::AlreadyApplied
+    ));
+    fs::remove_dir_all(top)?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct DirArgs {
+    /// octal permissions of the last directory, repeat for the ones above it:
+    /// --mode 755 --mode 700 on a/b/c is 755 for b and 700 for c
+    #[arg(short, long, value_parser = parse_mode)]
+    pub mode: Vec<u32>,
+    /// user name or uid of the directories given a mode, or the last one
+    #[arg(long)]
+    pub owner: Option<String>,
+    /// group name or gid of the directories given a mode, or the last one
+    #[arg(long)]
+    pub group: Option<String>,
+    /// remove everything in the last directory that is not kept
+    #[arg(long)]
+    pub purge: bool,
+    /// a name in the last directory that --purge leaves
+    #[arg(long, requires = "purge")]
+    pub keep: Vec<String>,
+}
+
+// the changes that make the tree match args, outermost directory first
+fn changes(path: &Path, args: &DirArgs) -> Result<Vec<(PathBuf, Change)>, ApplyError> {
+    if path.exists() && !path.is_dir() {
+        return Err(ApplyError::Error(format!(
+            "{:?} exists and is not a directory",
+            path
+        )));
+    }
+    let mut levels: Vec<&Path> = path
+        .ancestors()
+        .take_while(|d| !d.as_os_str().is_empty())
+        .collect();
+    levels.reverse();
+    if args.mode.len() > levels.len() {
+        return Err(ApplyError::UnExpectedArg(format!(
+            "{} modes for the {} directories of {:?}",
+            args.mode.len(),
+            levels.len(),
+            path
+        )));
+    }
+    // the directories given a mode, owner and group
+    let managed = levels.len() - args.mode.len().max(1);
+    let uid = args.owner.as_deref().map(uid_of).transpose()?;
+    let gid = args.group.as_deref().map(gid_of).transpose()?;
+    let missing = missing_dirs(path);
+    let mut changes = Vec::new();
+    for (i, level) in levels.into_iter().enumerate() {
+        let meta = if missing.iter().any(|m| m == level) {
+            changes.push((level.to_owned(), Change::Mkdir));
+            None
+        } else {
+            Some(level.metadata()?)
+        };
+        if i < managed {
+            continue;
+        }
+        if let Some(&mode) = args.mode.get(i - managed) {
+            if meta.as_ref().map(|m| m.mode() & 0o7777) != Some(mode) {
+                changes.push((level.to_owned(), Change::Chmod(mode)));
+            }
+        }
+        if let (Some(uid), Some(owner)) = (uid, &args.owner) {
+            if meta.as_ref().map(|m| m.uid()) != Some(uid) {
+                changes.push((level.to_owned(), Change::Chown(uid, owner.clone())));
+            }
+        }
+        if let (Some(gid), Some(group)) = (gid, &args.group) {
+            if meta.as_ref().map(|m| m.gid()) != Some(gid) {
+                changes.push((level.to_owned(), Change::Chgrp(gid, group.clone())));
+            }
+        }
+    }
+    if args.purge && path.is_dir() {
+        let mut unmanaged: Vec<PathBuf> = fs::read_dir(path)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| !args.keep.iter().any(|k| entry.file_name() == k.as_str()))
+            .map(|entry| entry.path())
+            .collect();
+        unmanaged.sort();
+        changes.extend(unmanaged.into_iter().map(|p| (p, Change::Remove)));
+    }
+    Ok(changes)
+}
+
+pub fn ensure_dir(mode: Mode, path: &Path, args: &DirArgs) -> Result<ActionResult, ApplyError> {
+    let changes = changes(path, args)?;
+    if changes.is_empty() {
+        log_no_change("dir", path);
+        return Ok(ActionResult::AlreadyApplied);
+    }
+    if let Some(first) = missing_dirs(path).first() {
+        can_write_to_parent_dir(first.clone())?;
+    } else if args.purge {
+        can_write_dir(path.to_path_buf())?;
+    }
+    apply_changes(mode, &changes)
+}
@@ -1,22 +1,24 @@
 use crate::cmd::Args;
+use crate::cmd::EnvOptions;
 use crate::cmd::Vars;
 use crate::cmd::VirtualFile;
 use crate::files::Mode;
 use crate::passive::log_cmd_action;
-use crate::passive::Verb;
+use crate::passive::{self, Event, Verb};
 use ansi_term::Colour::{Green, Red, Yellow};
 use applyerr::ApplyError;
 use cmd::exectable_full_path;
 use diff::create_or_diff;
 use diff::diff;
 use diff::update_from_template;
-use diff::DiffStatus;
 use env_logger::Env;
 use files::DestFile;
 use files::GenFile;
 use files::SrcFile;
 use log::trace;
 use log::LevelFilter;
+use secret;
+use serde_derive::Serialize;
 use simple_logger::SimpleLogger;
 use std::collections::{HashMap, VecDeque};
 use std::env;
@@ -25,24 +27,29 @@ use std::path::PathBuf;
 use std::process::Command;
 use std::slice::Iter;
 use std::str;
-use template::{generate_recommended_file, replace_line, replace_line2, ChangeString};
+use template::{generate_recommended_file, render};
 use userinput::ask;
 
-#[derive(Debug, Clone, Copy)]
+// Applied if anything was changed, Skipped if it would have been (passive, or
+// declined when interactive), AlreadyApplied if nothing differed
+#[derive(Debug, Clone, Copy, Serialize)]
 pub enum ActionResult {
     Applied,
     Skipped,
     AlreadyApplied,
+    NotApplied,
+    AlreadyUnapplied,
     //Created,
 }
-impl From<DiffStatus> for ActionResult {
-    fn from(ds: DiffStatus) -> Self {
-        match ds {
-            DiffStatus::NoChanges => Self::AlreadyApplied,
-            DiffStatus::NewFile => Self::Applied,
-            DiffStatus::Changed(_) => Self::Applied,
-            DiffStatus::Unsupported => Self::Skipped, // TODO: handle this
-            DiffStatus::Failed => Self::Skipped,      // TODO: handle this
+// see "Exit codes" in README.md
+impl ActionResult {
+    pub fn exit_code(&self, detailed: bool) -> i32 {
+        match (self, detailed) {
+            (ActionResult::NotApplied, _) => 1,
+            (_, false) => 0,
+            (ActionResult::AlreadyApplied, true) | (ActionResult::AlreadyUnapplied, true) => 0,
+            (ActionResult::Applied, true) => 2,
+            (ActionResult::Skipped, true) => 3,
         }
     }
 }
@@ -51,7 +58,7 @@ fn process_template_file(
     vars: Vars,
     template: &SrcFile,
     dest: &DestFile,
-) -> Result<DiffStatus, ApplyError> {
+) -> Result<ActionResult, ApplyError> {
     let gen = generate_recommended_file(vars, template)?;
     create_or_diff(mode, template, dest, &gen)
 }
@@ -65,8 +72,9 @@ fn process_template_file(
 fn test_execute_active() -> Result<(), ApplyError> {
     let always_true = VirtualFile::FsPath(PathBuf::from("/bin/true"));
     let always_false = VirtualFile::FsPath(PathBuf::from("/bin/false"));
-    execute_active(&always_true, Args::new(), &Vars::new())?;
-    match execute_active(&always_false, Args::new(), &Vars::new()) {
+    let env = EnvOptions::default();
+    execute_active(&always_true, Args::new(), &Vars::new(), &env)?;
+    match execute_active(&always_false, Args::new(), &Vars::new(), &env) {
         Err(e) => println!(
             "{} {}",
             Green.paint("/bin/false returned: "),
@@ -75,11 +83,15 @@ fn test_execute_active() -> Result<(), ApplyError> {
         _ => return Err(ApplyError::Error(String::from("OK not expected"))),
     }
     let echo_hello = VirtualFile::InMemory("#!/bin/sh\necho hello".into());
-    execute_active(
-        &echo_hello,
-        Args::new(),
-        &Vars::new(),
-    )?;
+    execute_active(&echo_hello, Args::new(), &Vars::new(), &env)?;
+    let mut vars = Vars::new();
+    vars.insert("KEY".into(), "value".into());
+    let test_env = VirtualFile::InMemory("test \"$KEY\" = value -a -z \"$HOME\"".into());
+    let clean = EnvOptions {
+        clean_env: true,
+        ..EnvOptions::default()
+    };
+    execute_active(&test_env, Args::new(), &vars, &clean)?;
     Ok(())
 }
 
@@ -87,17 +99,18 @@ fn execute_inactive(
     script: &VirtualFile,
     args: Args,
     vars: &Vars,
+    env: &EnvOptions,
 ) -> Result<ActionResult, ApplyError> {
     //        let exe_path = exectable_full_path(cmd)?;
     let filled_args = replace_all(&args, vars)?;
-    let cli = format!("{:?} {} {:?}", vars, script, filled_args);
+    let cli = format!("{} {} {:?}", env.display(vars), script, filled_args);
     log_cmd_action("run", Verb::Would, cli);
     Ok(ActionResult::Skipped)
 }
 fn replace_all(args: &[String], vars: &Vars) -> Result<Vec<String>, ApplyError> {
     let filled_args: Vec<String> = args
         .iter()
-        .map(|a| replace_line2(vars, a))
+        .map(|a| render(vars, a))
         .collect::<Result<Vec<String>, ApplyError>>()?;
     debug!("{:?}", filled_args);
     Ok(filled_args)
@@ -106,6 +119,7 @@ fn execute_active(
     script: &VirtualFile,
     args: Vec<String>,
     vars: &Vars,
+    env: &EnvOptions,
 ) -> Result<ActionResult, ApplyError> {
     let o = script.as_executable()?;
     let mut ps = Command::new(o.path());
@@ -117,51 +131,91 @@ fn execute_active(
         ps.args(filled_args);
     }
 
-    //ps.envs(vars);
+    if env.clean_env {
+        ps.env_clear();
+    }
+    ps.envs(env.resolved_env(vars, script)?);
     let r = ps.output();
-    let output = r.map_err(|e| {
-        ApplyError::ExecError(format!(
+    let output = r.map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => ApplyError::CommandNotFound(format!("{:?}", o.path())),
+        _ => ApplyError::ExecError(format!(
             "execute_active execute failed: {:?} {:?} {:?}",
             o.path(),
             script,
             e
-        ))
+        )),
     })?;
-    println!("{} {}", Green.paint("LIVE: run "), script);
-    io::stdout()
-        .write_all(&output.stdout)
-        .expect("error writing to stdout");
-    match output.status.code() {
+    if passive::is_text() {
+        passive::text(format!("{} {}", Green.paint("LIVE: run "), script));
+        io::stdout()
+            .write_all(secret::redact(&String::from_utf8_lossy(&output.stdout)).as_bytes())
+            .expect("error writing to stdout");
+        // why a failing step failed
+        io::stderr()
+            .write_all(secret::redact(&String::from_utf8_lossy(&output.stderr)).as_bytes())
+            .expect("error writing to stderr");
+    }
+    let result = match output.status.code() {
         Some(n) => {
             if n == 0 {
-                println!(
+                passive::text(format!(
                     "{} {}",
                     Green.paint("status code: "),
                     Green.paint(n.to_string())
-                );
+                ));
                 Ok(ActionResult::Applied)
             } else {
                 Err(ApplyError::NotZeroExit(n))
             }
         }
         None => Err(ApplyError::CmdExitedPrematurely),
-    }
+    };
+    passive::emit(Event {
+        verb: Some(Verb::Live),
+        action: String::from("run"),
+        script: Some(script.to_string()),
+        result: result.as_ref().ok().cloned(),
+        exit_code: output.status.code(),
+        stdout: Some(String::from_utf8_lossy(&output.stdout).into_owned()),
+        stderr: Some(String::from_utf8_lossy(&output.stderr).into_owned()),
+        error: result.as_ref().err().map(|e| e.to_string()),
+        ..Event::default()
+    });
+    result
 }
 
 fn execute_interactive(
     script: &VirtualFile,
     args: Args,
     vars: &Vars,
+    env: &EnvOptions,
 ) -> Result<ActionResult, ApplyError> {
     let filled_args = replace_all(&args, vars)?;
     let strargs = filled_args.join(" ");
-    match ask(&format!("run (y/n): {} {}", script, strargs)) {
+    match ask(&format!(
+        "run (y/n): {} {} {}",
+        env.display(vars),
+        script,
+        strargs
+    )) {
         'n' => {
-            println!("{} {} {}", Yellow.paint("SKIP: run "), script, strargs);
+            passive::text(format!(
+                "{} {} {}",
+                Yellow.paint("SKIP: run "),
+                script,
+                strargs
+            ));
+            passive::emit(Event {
+                verb: Some(Verb::Skipped),
+                action: String::from("run"),
+                script: Some(script.to_string()),
+                result: Some(ActionResult::Skipped),
+                ..Event::default()
+            });
             Ok(ActionResult::Skipped)
         }
-        'y' => execute_active(script, args, vars),
-        _ => execute_interactive(script, args, vars),
+        'y' => execute_active(script, args, vars, env),
+        _ => execute_interactive(script, args, vars, env),
     }
 }
 
@@ -170,14 +224,25 @@ pub fn execute(
     cmd: &VirtualFile,
     args: Args,
     vars: &Vars,
+    env: &EnvOptions,
 ) -> Result<ActionResult, ApplyError> {
     match mode {
-        Mode::Interactive => execute_interactive(cmd, args, vars),
-        Mode::Passive => execute_inactive(cmd, args, vars),
-        Mode::Active => execute_active(cmd, args, vars),
+        Mode::Interactive => execute_interactive(cmd, args, vars, env),
+        Mode::Passive => execute_inactive(cmd, args, vars, env),
+        Mode::Active => execute_active(cmd, args, vars, env),
     }
 }
 
+#[test]
+fn test_exit_code() {
+    assert_eq!(ActionResult::Applied.exit_code(false), 0);
+    assert_eq!(ActionResult::Applied.exit_code(true), 2);
+    assert_eq!(ActionResult::Skipped.exit_code(true), 3);
+    assert_eq!(ActionResult::AlreadyApplied.exit_code(true), 0);
+    assert_eq!(ActionResult::NotApplied.exit_code(false), 1);
+    assert_eq!(ApplyError::NotZeroExit(7).exit_code(), 10);
+    assert_eq!(ApplyError::VarNotFound("x".into()).exit_code(), 12);
+}
 #[test]
 fn test_do_action() -> Result<(), ApplyError> {
     let _ = env_logger::Builder::from_env(Env::default().default_filter_or("trace")).try_init();
@@ -195,19 +260,26 @@ pub(crate) fn do_template(
     maybe_data: Option<String>,
     maybe_in: Option<PathBuf>,
     output_file: DestFile,
-) -> Result<DiffStatus, ApplyError> {
-    let infile = match maybe_data {
-        Some(data) => VirtualFile::InMemory(data),
-        None => VirtualFile::FsPath(maybe_in.unwrap()), // TODO: check unwrap
-    };
+) -> Result<ActionResult, ApplyError> {
     debug!("vars {:#?}", vars);
-
-    let template_file = SrcFile::new(infile);
+    let template_file = template_source(maybe_data, maybe_in)?;
     process_template_file(mode, vars, &template_file, &output_file)
 }
+// the template text given inline, or the --infile path
+pub(crate) fn template_source(
+    maybe_data: Option<String>,
+    maybe_in: Option<PathBuf>,
+) -> Result<SrcFile, ApplyError> {
+    match (maybe_data, maybe_in) {
+        (Some(data), _) => Ok(SrcFile::new(VirtualFile::InMemory(data))),
+        (None, Some(path)) => Ok(SrcFile::new(VirtualFile::FsPath(path))),
+        (None, None) => Err(ApplyError::ExpectedArg(String::from("infile or data"))),
+    }
+}
 pub(crate) fn dryrun(
     mode: Mode,
     vars: Vars,
+    env: &EnvOptions,
     cmd_line: Vec<String>,
 ) -> Result<ActionResult, ApplyError> {
     let cmd_exe = &cmd_line[0];
@@ -222,5 +294,5 @@ pub(crate) fn dryrun(
     }
     debug!("args {:?}", args);
     debug!("do_action execute {:?} {:?} {:?}", mode, script, args);
-    execute(mode, &script, args, &vars)
+    execute(mode, &script, args, &vars, env)
 }
@@ -0,0 +1,316 @@
+use ansi_term::Colour::Yellow;
+use applyerr::ApplyError;
+use dryrun::ActionResult;
+use files::Mode;
+use fs::{can_write_file, gid_of, ids, uid_of};
+use link::{ensure_link, LinkArgs};
+use passive::Verb::{Live, Skipped, Would};
+use passive::{self, log_path_action, Event};
+use std::fs::{self, Metadata, OpenOptions, Permissions};
+use std::os::unix::fs::{lchown, symlink, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use userinput::ask;
+
+#[test]
+fn test_ensure_file() -> Result<(), ApplyError> {
+    let path = std::env::temp_dir().join("test_ensure_file.txt");
+    let _ = fs::remove_file(&path);
+    let args = FileArgs {
+        mode: Some(0o600),
+        owner: Some(ids().0.to_string()),
+        ..FileArgs::default()
+    };
+    assert!(matches!(
+        ensure_file(Mode::Passive, &path, &args)?,
+        ActionResult::Skipped
+    ));
+    assert!(!path.exists());
+    assert!(matches!(
+        ensure_file(Mode::Active, &path, &args)?,
+        ActionResult::Applied
+    ));
+    assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o7777, 0o600);
+    assert!(matches!(
+        ensure_file(Mode::Active, &path, &args)?,
+        ActionResult::AlreadyApplied
+    ));
+    let link = std::env::temp_dir().join("test_ensure_file.link");
+    let _ = fs::remove_file(&link);
+    let symlink = FileArgs {
+        state: State::Symlink,
+        target: Some(path.clone()),
+        owner: args.owner.clone(),
+        ..FileArgs::default()
+    };
+    assert!(matches!(
+        ensure_file(Mode::Active, &link, &symlink)?,
+        ActionResult::Applied
+    ));
+    assert_eq!(fs::read_link(&link)?, path);
+    assert!(matches!(
+        ensure_file(Mode::Active, &link, &symlink)?,
+        ActionResult::AlreadyApplied
+    ));
+    let with_mode = FileArgs {
+        mode: Some(0o600),
+        ..symlink
+    };
+    assert!(ensure_file(Mode::Active, &link, &with_mode).is_err());
+    fs::remove_file(&link)?;
+    let absent = FileArgs {
+        state: State::Absent,
+        ..FileArgs::default()
+    };
+    assert!(matches!(
+        ensure_file(Mode::Active, &path, &absent)?,
+        ActionResult::Applied
+    ));
+    assert!(!path.exists());
+    assert_eq!(parse_mode("0755"), Ok(0o755));
+    assert!(parse_mode("9").is_err());
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum State {
+    // a regular file, created empty when missing
+    #[default]
+    Present,
+    Absent,
+    Directory,
+    Symlink,
+}
+
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct FileArgs {
+    /// octal permissions, e.g. 755 or 0644
+    #[arg(short, long, value_parser = parse_mode)]
+    pub mode: Option<u32>,
+    /// user name or uid
+    #[arg(long)]
+    pub owner: Option<String>,
+    /// group name or gid
+    #[arg(long)]
+    pub group: Option<String>,
+    /// absent removes the path, a directory with everything in it
+    #[arg(long, value_enum, default_value_t = State::Present)]
+    pub state: State,
+    /// where a symlink points, as in the link command
+    #[arg(long, required_if_eq("state", "symlink"))]
+    pub target: Option<PathBuf>,
+}
+
+pub fn parse_mode(mode: &str) -> Result<u32, String> {
+    match u32::from_str_radix(mode.trim_start_matches("0o"), 8) {
+        Ok(bits) if bits <= 0o7777 => Ok(bits),
+        _ => Err(format!("{} is not an octal mode like 0644", mode)),
+    }
+}
+
+// one difference between the path and what it should be
+#[derive(Debug)]
+pub enum Change {
+    Create,
+    Mkdir,
+    Symlink(PathBuf),
+    Remove,
+    // moved aside to the given path
+    Backup(PathBuf),
+    Chmod(u32),
+    Chown(u32, String),
+    Chgrp(u32, String),
+}
+
+impl Change {
+    pub fn action(&self) -> String {
+        match self {
+            Change::Create => String::from("create"),
+            Change::Mkdir => String::from("mkdir"),
+            Change::Symlink(src) => format!("symlink {}", src.display()),
+            Change::Remove => String::from("remove"),
+            Change::Backup(to) => format!("backup to {}", to.display()),
+            Change::Chmod(mode) => format!("chmod {:04o}", mode),
+            Change::Chown(_, owner) => format!("chown {}", owner),
+            Change::Chgrp(_, group) => format!("chgrp {}", group),
+        }
+    }
+    pub fn apply(&self, path: &Path) -> Result<(), ApplyError> {
+        let failed =
+            |e: std::io::Error| ApplyError::Error(format!("{} {:?} {}", self.action(), path, e));
+        match self {
+            Change::Create => OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)
+                .map(|_| ())
+                .map_err(|e| ApplyError::FileCreateError(format!("{:?} {}", path, e))),
+            Change::Mkdir => fs::create_dir_all(path).map_err(failed),
+            Change::Symlink(src) => {
+                // a symlink pointing elsewhere is replaced
+                if path.symlink_metadata().is_ok() {
+                    fs::remove_file(path).map_err(failed)?;
+                }
+                symlink(src, path).map_err(failed)
+            }
+            Change::Remove => match path.symlink_metadata() {
+                Ok(meta) if meta.is_dir() => fs::remove_dir_all(path).map_err(failed),
+                _ => fs::remove_file(path).map_err(failed),
+            },
+            Change::Backup(to) => fs::rename(path, to).map_err(failed),
+            Change::Chmod(mode) => {
+                fs::set_permissions(path, Permissions::from_mode(*mode)).map_err(failed)
+            }
+            Change::Chown(uid, _) => lchown(path, Some(*uid), None).map_err(failed),
+            Change::Chgrp(gid, _) => lchown(path, None, Some(*gid)).map_err(failed),
+        }
+    }
+}
+
+// the changes that make path match args, none when it already does
+fn changes(path: &Path, args: &FileArgs) -> Result<Vec<Change>, ApplyError> {
+    let meta = path.symlink_metadata().ok();
+    let mut changes = Vec::new();
+    match (args.state, &meta) {
+        (State::Absent, None) => return Ok(changes),
+        (State::Absent, Some(_)) => {
+            changes.push(Change::Remove);
+            return Ok(changes);
+        }
+        (State::Present, None) => changes.push(Change::Create),
+        (State::Present, Some(m)) if !m.is_file() => {
+            return Err(ApplyError::NotAFile(path.to_owned()))
+        }
+        (State::Directory, None) => changes.push(Change::Mkdir),
+        (State::Directory, Some(m)) if !m.is_dir() => {
+            return Err(ApplyError::Error(format!(
+                "{:?} exists and is not a directory",
+                path
+            )))
+        }
+        _ => (),
+    }
+    // a path about to be created is compared as if it had no attributes yet
+    let current: Option<&Metadata> = meta.as_ref().filter(|_| changes.is_empty());
+    if let Some(mode) = args.mode {
+        if current.map(|m| m.mode() & 0o7777) != Some(mode) {
+            changes.push(Change::Chmod(mode));
+        }
+    }
+    if let Some(owner) = &args.owner {
+        let uid = uid_of(owner)?;
+        if current.map(|m| m.uid()) != Some(uid) {
+            changes.push(Change::Chown(uid, owner.clone()));
+        }
+    }
+    if let Some(group) = &args.group {
+        let gid = gid_of(group)?;
+        if current.map(|m| m.gid()) != Some(gid) {
+            changes.push(Change::Chgrp(gid, group.clone()));
+        }
+    }
+    Ok(changes)
+}
+
+pub fn ensure_file(mode: Mode, path: &Path, args: &FileArgs) -> Result<ActionResult, ApplyError> {
+    if args.state == State::Symlink {
+        return ensure_symlink(mode, path, args);
+    }
+    let changes = changes(path, args)?;
+    if changes.is_empty() {
+        log_no_change("file", path);
+        return Ok(ActionResult::AlreadyApplied);
+    }
+    if matches!(changes[0], Change::Create | Change::Mkdir | Change::Remove) {
+        can_write_file(path.to_path_buf())?;
+    }
+    let changes: Vec<(PathBuf, Change)> = changes
+        .into_iter()
+        .map(|change| (path.to_owned(), change))
+        .collect();
+    apply_changes(mode, &changes)
+}
+// the link as link makes it, then its own owner and group
+fn ensure_symlink(mode: Mode, path: &Path, args: &FileArgs) -> Result<ActionResult, ApplyError> {
+    if args.mode.is_some() {
+        return Err(ApplyError::UnExpectedArg(String::from(
+            "mode for a symlink",
+        )));
+    }
+    let target = args
+        .target
+        .clone()
+        .ok_or_else(|| ApplyError::ExpectedArg(String::from("target")))?;
+    let link = ensure_link(
+        mode,
+        path,
+        &LinkArgs {
+            target,
+            ..LinkArgs::default()
+        },
+    )?;
+    // a link that was skipped has no owner to change
+    if path.symlink_metadata().is_err() && !matches!(mode, Mode::Passive) {
+        return Ok(link);
+    }
+    let changes: Vec<(PathBuf, Change)> = changes(path, args)?
+        .into_iter()
+        .map(|change| (path.to_owned(), change))
+        .collect();
+    if changes.is_empty() {
+        return Ok(link);
+    }
+    Ok(match (link, apply_changes(mode, &changes)?) {
+        (ActionResult::Applied, _) | (_, ActionResult::Applied) => ActionResult::Applied,
+        _ => ActionResult::Skipped,
+    })
+}
+pub fn log_no_change(action: &str, path: &Path) {
+    passive::text(format!(
+        "{} {}",
+        Yellow.paint("NO CHANGE: "),
+        Yellow.paint(path.display().to_string())
+    ));
+    passive::emit(Event {
+        action: action.to_owned(),
+        dest: Some(path.display().to_string()),
+        result: Some(ActionResult::AlreadyApplied),
+        ..Event::default()
+    });
+}
+// in order, each logged as Would, Live or Skipped
+pub fn apply_changes(
+    mode: Mode,
+    changes: &[(PathBuf, Change)],
+) -> Result<ActionResult, ApplyError> {
+    let mut result = ActionResult::Skipped;
+    for (path, change) in changes {
+        let action = change.action();
+        let apply = match mode {
+            Mode::Passive => {
+                log_path_action(&action, Would, path);
+                continue;
+            }
+            Mode::Active => true,
+            Mode::Interactive => loop {
+                match ask(&format!("{} {} (y/n)", action, path.display())) {
+                    'y' => break true,
+                    'n' => break false,
+                    _ => continue,
+                }
+            },
+        };
+        if apply {
+            log_path_action(&action, Live, path);
+            change.apply(path)?;
+            result = ActionResult::Applied;
+        } else {
+            log_path_action(&action, Skipped, path);
+            // what is created is needed by the changes after it
+            if matches!(change, Change::Create | Change::Mkdir | Change::Symlink(_)) {
+                break;
+            }
+        }
+    }
+    Ok(result)
+}
@@ -1,4 +1,5 @@
 use log::trace;
+use serde_derive::Deserialize;
 use std::ffi::OsStr;
 use std::fmt;
 use std::fs::File;
@@ -10,7 +11,8 @@ use crate::applyerr::ApplyError;
 use crate::cmd::OpenFileHolder;
 use crate::cmd::VirtualFile;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Mode {
     Active,
     Passive,
@@ -2,8 +2,11 @@ extern crate libc;
 use applyerr::ApplyError;
 use env_logger::Env;
 use files::Mode;
+use passive::{log_path_action, Verb};
 use seahorse::App;
+use sha2::{Digest, Sha256};
 use std::ffi::CString;
+use std::io::Read;
 use std::{env, path::Path, path::PathBuf};
 use userinput::ask;
 
@@ -151,33 +154,119 @@ pub fn create_parent_dir(mode: Mode, child: PathBuf) -> Result<(), ApplyError> {
         ))),
     }
 }
+// each missing directory is logged, Passive checks the nearest existing one is writable
 pub fn create_dir(mode: Mode, dir: PathBuf) -> Result<(), ApplyError> {
     trace!("create_dir{:?}", dir);
-    if dir.exists() {
-        Ok(())
-    } else {
-        let ans = match mode {
-            Mode::Passive => 'n',
-            Mode::Active => 'y',
-            Mode::Interactive => ask(format!("create directory {} (y/n)", dir.display()).as_str()),
-        };
-        match ans {
-            'n' => can_create_parent_dir(dir),
-            'y' => {
-                println!("mkdirs {:?}", dir);
-                std::fs::create_dir_all(dir.clone()).map_err(|e| {
-                    ApplyError::InsufficientPrivileges(format!(
-                        "create_dir_all {:?} {:?}",
-                        dir.clone(),
-                        e
-                    ))
-                })
+    let missing = missing_dirs(&dir);
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let create = match mode {
+        Mode::Passive => {
+            for d in &missing {
+                log_path_action("mkdir", Verb::Would, d);
+            }
+            return can_write_to_parent_dir(missing[0].clone());
+        }
+        Mode::Active => true,
+        Mode::Interactive => loop {
+            match ask(format!("create directory {} (y/n)", dir.display()).as_str()) {
+                'y' => break true,
+                'n' => break false,
+                _ => continue, //repeat the question
             }
-            _ => create_dir(mode, dir), //repeat the question
+        },
+    };
+    for d in &missing {
+        if !create {
+            log_path_action("mkdir", Verb::Skipped, d);
+            continue;
         }
+        log_path_action("mkdir", Verb::Live, d);
+        std::fs::create_dir(d).map_err(|e| {
+            ApplyError::InsufficientPrivileges(format!("create_dir {:?} {:?}", d, e))
+        })?;
     }
+    Ok(())
+}
+// dir and its ancestors that do not exist yet, outermost first. The empty parent of a
+// relative path is the current dir, it always exists
+pub fn missing_dirs(dir: &Path) -> Vec<PathBuf> {
+    let mut missing: Vec<PathBuf> = dir
+        .ancestors()
+        .take_while(|d| !d.as_os_str().is_empty() && !d.exists())
+        .map(Path::to_path_buf)
+        .collect();
+    missing.reverse();
+    missing
+}
+// a user name or uid
+pub fn uid_of(user: &str) -> Result<u32, ApplyError> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    let name = CString::new(user).map_err(|_| ApplyError::NameNotFound(user.to_owned()))?;
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut found = std::ptr::null_mut();
+    let rc = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut pwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    if rc == 0 && !found.is_null() {
+        Ok(pwd.pw_uid)
+    } else {
+        Err(ApplyError::NameNotFound(format!("user {}", user)))
+    }
+}
+// a group name or gid
+pub fn gid_of(group: &str) -> Result<u32, ApplyError> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let name = CString::new(group).map_err(|_| ApplyError::NameNotFound(group.to_owned()))?;
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut found = std::ptr::null_mut();
+    let rc = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut grp,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    if rc == 0 && !found.is_null() {
+        Ok(grp.gr_gid)
+    } else {
+        Err(ApplyError::NameNotFound(format!("group {}", group)))
+    }
+}
+// the effective uid and gid, what a new file is owned by
+pub fn ids() -> (u32, u32) {
+    unsafe { (libc::geteuid(), libc::getegid()) }
 }
 pub fn clean_tmp<P: AsRef<Path>>(path: P) -> Result<(), ApplyError> {
     std::fs::remove_file(path)?;
     Ok(())
 }
+// sha256 of the contents as hex, read in chunks so large files are not loaded whole
+pub fn file_hash<P: AsRef<Path>>(path: P) -> Result<String, ApplyError> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
@@ -0,0 +1,247 @@
+use ansi_term::Colour::Yellow;
+use applyerr::ApplyError;
+use diff::{diff, DiffStatus, DiffText};
+use dryrun::ActionResult;
+use files::{DestFile, GenFile, Mode};
+use fs::{can_write_file, clean_tmp, create_parent_dir};
+use passive::Verb::{self, Live, Skipped, Would};
+use passive::{self, color_from_verb, Event};
+use regex::Regex;
+use std::io::Write;
+use userinput::ask;
+
+#[test]
+fn test_edit() -> Result<(), ApplyError> {
+    let text = "a=1\n#b=2\nc=3\n";
+    let spec = |line: &str, args: &[&str]| {
+        let mut line_args = LineArgs {
+            line: Some(line.to_owned()),
+            ..LineArgs::default()
+        };
+        for pair in args.chunks(2) {
+            let value = Some(pair[1].to_owned());
+            match pair[0] {
+                "regex" => line_args.regex = value,
+                "after" => line_args.insert_after = value,
+                "before" => line_args.insert_before = value,
+                _ => line_args.state = State::Absent,
+            }
+        }
+        line_args.spec()
+    };
+    assert_eq!(edit(text, &spec("a=1", &[])?), text);
+    assert_eq!(edit(text, &spec("d=4", &[])?), "a=1\n#b=2\nc=3\nd=4\n");
+    assert_eq!(edit("a=1", &spec("d=4", &[])?), "a=1\nd=4\n");
+    assert_eq!(
+        edit(text, &spec("b=5", &["regex", "^#?b="])?),
+        "a=1\nb=5\nc=3\n"
+    );
+    assert_eq!(edit(text, &spec("c=3", &["regex", "^d="])?), text);
+    assert_eq!(
+        edit(text, &spec("b=5", &["after", "^a="])?),
+        "a=1\nb=5\n#b=2\nc=3\n"
+    );
+    assert_eq!(
+        edit(text, &spec("b=5", &["before", "^c="])?),
+        "a=1\n#b=2\nb=5\nc=3\n"
+    );
+    assert_eq!(
+        edit(text, &spec("c=3", &["state", "absent"])?),
+        "a=1\n#b=2\n"
+    );
+    assert_eq!(
+        edit(text, &spec("", &["regex", "^#", "state", "absent"])?),
+        "a=1\nc=3\n"
+    );
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum State {
+    #[default]
+    Present,
+    Absent,
+}
+
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct LineArgs {
+    /// the line, vars are filled like a template, as in the regexes
+    #[arg(short, long, required_unless_present = "regex")]
+    pub line: Option<String>,
+    /// absent removes the line, or every line matching --regex
+    #[arg(long, value_enum, default_value_t = State::Present)]
+    pub state: State,
+    /// the last matching line is replaced by --line
+    #[arg(short, long)]
+    pub regex: Option<String>,
+    /// a new line goes after the last line matching this, or at the end
+    #[arg(long, conflicts_with = "insert_before")]
+    pub insert_after: Option<String>,
+    /// a new line goes before the first line matching this, or at the end
+    #[arg(long)]
+    pub insert_before: Option<String>,
+    /// create the file when it does not exist
+    #[arg(long)]
+    pub create: bool,
+}
+
+pub struct LineSpec {
+    line: Option<String>,
+    state: State,
+    regex: Option<Regex>,
+    insert_after: Option<Regex>,
+    insert_before: Option<Regex>,
+    create: bool,
+}
+
+impl LineArgs {
+    pub fn spec(&self) -> Result<LineSpec, ApplyError> {
+        let compile = |re: &Option<String>| -> Result<Option<Regex>, ApplyError> {
+            re.as_deref()
+                .map(|re| {
+                    Regex::new(re).map_err(|e| ApplyError::Error(format!("regex {}: {}", re, e)))
+                })
+                .transpose()
+        };
+        if self.state == State::Present && self.line.is_none() {
+            return Err(ApplyError::ExpectedArg(String::from("line")));
+        }
+        Ok(LineSpec {
+            line: self.line.clone(),
+            state: self.state,
+            regex: compile(&self.regex)?,
+            insert_after: compile(&self.insert_after)?,
+            insert_before: compile(&self.insert_before)?,
+            create: self.create,
+        })
+    }
+}
+
+// the text with the line present or absent
+fn edit(text: &str, spec: &LineSpec) -> String {
+    let mut lines: Vec<&str> = text.lines().collect();
+    let line = spec.line.as_deref().unwrap_or("");
+    let matches = |l: &str| match &spec.regex {
+        Some(re) => re.is_match(l),
+        None => l == line,
+    };
+    match spec.state {
+        State::Absent => lines.retain(|l| !matches(l)),
+        State::Present => match lines.iter().rposition(|l| matches(l)) {
+            Some(i) => lines[i] = line,
+            // already there, e.g. after an earlier run replaced what --regex matched
+            None if lines.contains(&line) => (),
+            None => {
+                let at = match (&spec.insert_after, &spec.insert_before) {
+                    (Some(re), _) => lines.iter().rposition(|l| re.is_match(l)).map(|i| i + 1),
+                    (_, Some(re)) => lines.iter().position(|l| re.is_match(l)),
+                    _ => None,
+                };
+                lines.insert(at.unwrap_or(lines.len()), line);
+            }
+        },
+    }
+    let mut edited = lines.join("\n");
+    if !edited.is_empty() {
+        edited.push('\n');
+    }
+    // only the change, not a missing newline at the end
+    if edited.trim_end_matches('\n') == text.trim_end_matches('\n') {
+        return text.to_owned();
+    }
+    edited
+}
+
+pub fn ensure_line(
+    mode: Mode,
+    spec: &LineSpec,
+    dest: &DestFile,
+) -> Result<ActionResult, ApplyError> {
+    can_write_file(dest.path())?;
+    let text = match std::fs::read_to_string(dest.path()) {
+        Ok(text) => text,
+        Err(_) if !dest.path().exists() && (spec.create || spec.state == State::Absent) => {
+            String::new()
+        }
+        Err(_) if !dest.path().exists() => {
+            return Err(ApplyError::PathBufNotFound(dest.path()));
+        }
+        Err(e) => return Err(ApplyError::IoError(e)),
+    };
+    let edited = edit(&text, spec);
+    if edited == text {
+        passive::text(format!(
+            "{} {}",
+            Yellow.paint("NO CHANGE: "),
+            Yellow.paint(dest.to_string())
+        ));
+        passive::emit(Event {
+            action: String::from("line"),
+            dest: Some(dest.to_string()),
+            result: Some(ActionResult::AlreadyApplied),
+            ..Event::default()
+        });
+        return Ok(ActionResult::AlreadyApplied);
+    }
+    let gen = GenFile::new()?;
+    gen.open()?.write_all(edited.as_bytes())?;
+    let difftext = match diff(gen.path(), dest.path()) {
+        DiffStatus::Changed(difftext) => Some(difftext),
+        _ => None,
+    };
+    let result = match mode {
+        Mode::Passive => {
+            log_line_action(Would, dest, difftext.as_ref());
+            Ok(ActionResult::Skipped)
+        }
+        Mode::Active => write_line(dest, &edited),
+        Mode::Interactive => line_interactive(dest, &edited, difftext.as_ref()),
+    };
+    clean_tmp(gen.path())?;
+    result
+}
+fn line_interactive(
+    dest: &DestFile,
+    edited: &str,
+    difftext: Option<&DiffText>,
+) -> Result<ActionResult, ApplyError> {
+    match ask(&format!("change {} (y)es / (n)o / (d)iff", dest)) {
+        'y' => write_line(dest, edited),
+        'n' => {
+            log_line_action(Skipped, dest, None);
+            Ok(ActionResult::Skipped)
+        }
+        'd' => {
+            log_line_action(Would, dest, difftext);
+            line_interactive(dest, edited, difftext)
+        }
+        _ => line_interactive(dest, edited, difftext),
+    }
+}
+// written in place so the file keeps its owner and permissions
+fn write_line(dest: &DestFile, edited: &str) -> Result<ActionResult, ApplyError> {
+    create_parent_dir(Mode::Active, dest.path())?;
+    log_line_action(Live, dest, None);
+    std::fs::write(dest.path(), edited)
+        .map_err(|e| ApplyError::FileWriteError(format!("{} {:?}", dest, e)))?;
+    Ok(ActionResult::Applied)
+}
+fn log_line_action(verb: Verb, dest: &DestFile, difftext: Option<&DiffText>) {
+    let color = color_from_verb(verb);
+    passive::text(format!(
+        "{}: {}: {}",
+        color.paint(verb.to_string()),
+        color.paint("line"),
+        color.paint(dest.to_string())
+    ));
+    if let Some(difftext) = difftext {
+        passive::text(difftext.colored());
+    }
+    passive::emit(Event {
+        verb: Some(verb),
+        action: String::from("line"),
+        dest: Some(dest.to_string()),
+        diff: difftext.map(|d| d.to_string()),
+        ..Event::default()
+    });
+}
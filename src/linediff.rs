@@ -0,0 +1,179 @@
+// Myers line diff rendered as a unified diff
+
+#[test]
+fn test_unified_diff() {
+    let old = "a\nb\nc\nd\ne\nf\ng\nh\n";
+    let new = "a\nb\nC\nd\ne\nf\ng\nh\ni\n";
+    let expected = "\
+--- old
++++ new
+@@ -2,3 +2,3 @@
+ b
+-c
++C
+ d
+@@ -8,1 +8,2 @@
+ h
++i
+";
+    assert_eq!(
+        unified_diff(old, new, "old", "new", 1),
+        Some(expected.into())
+    );
+    assert_eq!(
+        unified_diff("x\n", "x", "old", "new", 3).map(|d| d.lines().count()),
+        Some(6)
+    );
+    assert_eq!(
+        unified_diff("ä\n", "ä\n", "old", "new", 3),
+        Some("--- old\n+++ new\n".into())
+    );
+    // every line differs, too costly to diff
+    let old: String = (0..8000).map(|i| format!("old {}\n", i)).collect();
+    let new: String = (0..8000).map(|i| format!("new {}\n", i)).collect();
+    assert_eq!(unified_diff(&old, &new, "old", "new", 3), None);
+    let one = old.replacen("old 4000\n", "new 4000\n", 1);
+    assert!(unified_diff(&old, &one, "old", "new", 3).is_some());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+// (op, index in old, index in new); for inserts and deletes the other index is the position
+type Edit = (Op, usize, usize);
+
+// edit distance times lines, beyond it a diff takes too much time and memory
+const MAX_COST: usize = 5_000_000;
+
+// None when the cost goes over MAX_COST
+fn edit_script(a: &[&str], b: &[&str]) -> Option<Vec<Edit>> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max = n + m;
+    let offset = max as usize;
+    let at = |k: isize| (k + offset as isize) as usize;
+    let mut v = vec![0isize; 2 * offset + 2];
+    // v[-d..=d] before each step d, all the backtrack reads
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    'search: for d in 0..=max {
+        if d as usize * offset > MAX_COST {
+            return None;
+        }
+        trace.push(v[at(-d)..=at(d)].to_vec());
+        let mut k = -d;
+        while k <= d {
+            let mut x = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+                v[at(k + 1)]
+            } else {
+                v[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[at(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+            k += 2;
+        }
+    }
+    let mut edits: Vec<Edit> = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| (k + d) as usize;
+        let k = x - y;
+        let (prev_x, prev_y) = if d == 0 {
+            (0, 0)
+        } else {
+            let prev_k = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+                k + 1
+            } else {
+                k - 1
+            };
+            (v[at(prev_k)], v[at(prev_k)] - prev_k)
+        };
+        while x > prev_x && y > prev_y {
+            edits.push((Op::Equal, (x - 1) as usize, (y - 1) as usize));
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            if x == prev_x {
+                edits.push((Op::Insert, x as usize, (y - 1) as usize));
+            } else {
+                edits.push((Op::Delete, (x - 1) as usize, y as usize));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    edits.reverse();
+    Some(edits)
+}
+
+fn push_line(out: &mut String, prefix: char, line: &str) {
+    out.push(prefix);
+    match line.strip_suffix('\n') {
+        Some(l) => {
+            out.push_str(l);
+            out.push('\n');
+        }
+        None => {
+            out.push_str(line);
+            out.push_str("\n\\ No newline at end of file\n");
+        }
+    }
+}
+
+// just the ---/+++ header lines when old == new, None when too costly to diff
+pub fn unified_diff(
+    old: &str,
+    new: &str,
+    old_name: &str,
+    new_name: &str,
+    context: usize,
+) -> Option<String> {
+    let a: Vec<&str> = old.split_inclusive('\n').collect();
+    let b: Vec<&str> = new.split_inclusive('\n').collect();
+    let edits = edit_script(&a, &b)?;
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    let changes: Vec<usize> = (0..edits.len())
+        .filter(|&i| edits[i].0 != Op::Equal)
+        .collect();
+    let mut i = 0;
+    while i < changes.len() {
+        // merge the next change if at most 2 * context equal lines are in between
+        let start = changes[i].saturating_sub(context);
+        let mut last = changes[i];
+        while i + 1 < changes.len() && changes[i + 1] - last <= 2 * context + 1 {
+            i += 1;
+            last = changes[i];
+        }
+        let end = (last + context + 1).min(edits.len());
+        let hunk = &edits[start..end];
+        let old_count = hunk.iter().filter(|e| e.0 != Op::Insert).count();
+        let new_count = hunk.iter().filter(|e| e.0 != Op::Delete).count();
+        let old_start = hunk[0].1 + if old_count > 0 { 1 } else { 0 };
+        let new_start = hunk[0].2 + if new_count > 0 { 1 } else { 0 };
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_start, old_count, new_start, new_count
+        ));
+        for (op, ai, bi) in hunk {
+            match op {
+                Op::Equal => push_line(&mut out, ' ', a[*ai]),
+                Op::Delete => push_line(&mut out, '-', a[*ai]),
+                Op::Insert => push_line(&mut out, '+', b[*bi]),
+            }
+        }
+        i += 1;
+    }
+    Some(out)
+}
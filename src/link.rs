@@ -0,0 +1,174 @@
+use ansi_term::Colour::Yellow;
+use applyerr::ApplyError;
+use dryrun::ActionResult;
+use file::{apply_changes, log_no_change, Change};
+use files::Mode;
+use fs::can_write_file;
+use passive::Verb::Skipped;
+use passive::{self, log_path_action, Event};
+use std::fs;
+use std::path::{Path, PathBuf};
+use userinput::ask;
+
+#[test]
+fn test_ensure_link() -> Result<(), ApplyError> {
+    let dir = std::env::temp_dir().join("test_ensure_link");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir(&dir)?;
+    let path = dir.join("current");
+    let args = |target: &str, backup: bool| LinkArgs {
+        target: PathBuf::from(target),
+        force: false,
+        backup,
+    };
+    assert!(matches!(
+        ensure_link(Mode::Active, &path, &args("v1", false))?,
+        ActionResult::Applied
+    ));
+    assert_eq!(fs::read_link(&path)?, PathBuf::from("v1"));
+    assert!(matches!(
+        ensure_link(Mode::Active, &path, &args("v1", false))?,
+        ActionResult::AlreadyApplied
+    ));
+    assert!(matches!(
+        ensure_link(Mode::Active, &path, &args("v2", false))?,
+        ActionResult::Applied
+    ));
+    assert_eq!(fs::read_link(&path)?, PathBuf::from("v2"));
+    fs::remove_file(&path)?;
+    fs::write(&path, "in the way")?;
+    assert!(ensure_link(Mode::Active, &path, &args("v2", false)).is_err());
+    assert!(matches!(
+        ensure_link(Mode::Active, &path, &args("v2", true))?,
+        ActionResult::Applied
+    ));
+    assert_eq!(fs::read_to_string(backup_path(&path))?, "in the way");
+    fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct LinkArgs {
+    /// where the link points, relative to the link's directory unless absolute
+    #[arg(short, long)]
+    pub target: PathBuf,
+    /// replace a file or directory at the path
+    #[arg(long, conflicts_with = "backup")]
+    pub force: bool,
+    /// move a file or directory at the path to <path>.bak
+    #[arg(long)]
+    pub backup: bool,
+}
+
+pub fn backup_path(path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.bak", path.display()))
+}
+
+// what is at the link's path
+enum Found {
+    Missing,
+    Link(PathBuf),
+    Other(&'static str),
+}
+
+impl Found {
+    fn describe(&self, path: &Path) -> String {
+        match self {
+            Found::Missing => String::from("missing"),
+            Found::Link(current) if !resolve(path, current).exists() => {
+                format!("a dangling link to {}", current.display())
+            }
+            Found::Link(current) => format!("a link to {}", current.display()),
+            Found::Other(what) => what.to_string(),
+        }
+    }
+}
+
+pub fn ensure_link(mode: Mode, path: &Path, args: &LinkArgs) -> Result<ActionResult, ApplyError> {
+    let target = &args.target;
+    let found = match path.symlink_metadata() {
+        Err(_) => Found::Missing,
+        Ok(meta) if meta.file_type().is_symlink() => Found::Link(fs::read_link(path)?),
+        Ok(meta) if meta.is_dir() => Found::Other("a directory"),
+        Ok(_) => Found::Other("a file"),
+    };
+    if !resolve(path, target).exists() {
+        log_dangling(path, target);
+    }
+    if matches!(&found, Found::Link(current) if current == target) {
+        log_no_change("link", path);
+        return Ok(ActionResult::AlreadyApplied);
+    }
+    can_write_file(path.to_path_buf())?;
+    let link = Change::Symlink(target.clone());
+    let mut changes = Vec::new();
+    let mode = match (&found, mode) {
+        (Found::Missing, _) => mode,
+        (_, Mode::Interactive) => {
+            match ask_replace(path, &found.describe(path)) {
+                // a link is replaced by the new one
+                Some(Change::Remove) if matches!(found, Found::Link(_)) => (),
+                Some(change) => changes.push((path.to_owned(), change)),
+                None => {
+                    log_path_action(&link.action(), Skipped, path);
+                    return Ok(ActionResult::Skipped);
+                }
+            }
+            // already asked
+            Mode::Active
+        }
+        _ if args.backup => {
+            changes.push((path.to_owned(), Change::Backup(backup_path(path))));
+            mode
+        }
+        // a link holds no data, it is replaced without --force
+        (Found::Link(_), _) => mode,
+        _ if args.force => {
+            changes.push((path.to_owned(), Change::Remove));
+            mode
+        }
+        _ => {
+            return Err(ApplyError::Error(format!(
+                "{:?} is {}, use --force or --backup",
+                path,
+                found.describe(path)
+            )))
+        }
+    };
+    changes.push((path.to_owned(), link));
+    apply_changes(mode, &changes)
+}
+// None to skip, Some(Remove) to replace or Some(Backup)
+fn ask_replace(path: &Path, found: &str) -> Option<Change> {
+    loop {
+        match ask(&format!(
+            "{} is {} (r)eplace / (b)ackup / (s)kip",
+            path.display(),
+            found
+        )) {
+            'r' => return Some(Change::Remove),
+            'b' => return Some(Change::Backup(backup_path(path))),
+            's' => return None,
+            _ => continue,
+        }
+    }
+}
+// a relative target is relative to the link's directory
+fn resolve(path: &Path, target: &Path) -> PathBuf {
+    path.parent().unwrap_or(Path::new("")).join(target)
+}
+// the link is still made, the target may come later
+fn log_dangling(path: &Path, target: &Path) {
+    passive::text(format!(
+        "{} {} -> {}",
+        Yellow.paint("DANGLING: "),
+        Yellow.paint(path.display().to_string()),
+        Yellow.paint(target.display().to_string())
+    ));
+    passive::emit(Event {
+        action: String::from("dangling"),
+        dest: Some(path.display().to_string()),
+        value: Some(target.display().to_string()),
+        ..Event::default()
+    });
+}
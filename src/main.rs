@@ -1,6 +1,7 @@
 #![allow(unused_imports)]
 
 extern crate ansi_term;
+extern crate base64;
 extern crate config;
 extern crate dirs;
 extern crate env_logger;
@@ -13,10 +14,13 @@ extern crate clap;
 extern crate regex;
 extern crate seahorse;
 extern crate serde_derive;
+extern crate serde_yaml;
+extern crate sha2;
 extern crate simple_logger;
 extern crate thiserror;
 extern crate which;
 
+use crate::cmd::EnvOptions;
 use crate::cmd::Vars;
 use anyhow::Error;
 use anyhow::{Context, Result};
@@ -26,6 +30,7 @@ use config::Config;
 use dryrun::ActionResult;
 use files::DestFile;
 use files::Mode;
+use files::SrcFile;
 
 use ansi_term::Colour::{Green, Red, Yellow};
 use seahorse::{Flag, FlagType};
@@ -40,17 +45,38 @@ use std::{
 mod applyerr;
 pub mod passive;
 use applyerr::ApplyError;
+mod action;
 mod apply;
 mod cmd;
 mod configfile;
+mod copy;
 mod diff;
+mod dir;
 mod dryrun;
+mod file;
 mod files;
 mod fs;
+mod line;
+mod linediff;
+mod link;
+mod manifest;
+mod playbook;
+mod resolver;
+mod secret;
+mod store;
 mod template;
 mod userinput;
+mod varsfile;
+mod yamledit;
 
-use apply::execute_apply;
+use action::Action;
+use configfile::{Color, Settings};
+use manifest::Manifest;
+use passive::{Event, Format};
+use playbook::Playbook;
+use template::Undefined;
+
+use apply::execute_script;
 
 use crate::cmd::VirtualFile;
 
@@ -60,8 +86,55 @@ fn test_appply() -> Result<(), ApplyError> {
     let is_applied = VirtualFile::InMemory(String::from("#!/bin/sh\ntest -f test1.tmp"));
 
     let name_config: HashMap<String, String> = HashMap::new();
-    do_is_applied(name_config.clone(), &is_applied)?;
-    do_apply(name_config, &apply_script, files::Mode::Active)?;
+    let env = EnvOptions::default();
+    do_is_applied(name_config.clone(), &env, &is_applied)?;
+    do_apply(
+        name_config.clone(),
+        &env,
+        &apply_script,
+        files::Mode::Active,
+    )?;
+    // a broken check is an error, not Unapplied
+    let missing = VirtualFile::FsPath(PathBuf::from("example/no-such-is-applied"));
+    let err = do_is_applied(name_config, &env, &missing).unwrap_err();
+    assert_eq!(err.exit_code(), 13);
+    Ok(())
+}
+#[test]
+fn test_unapply() -> Result<(), ApplyError> {
+    let vars = Vars::new();
+    let env = EnvOptions::default();
+    let is_applied = || VirtualFile::InMemory(String::from("test -f test2.tmp"));
+    let undo = || VirtualFile::InMemory(String::from("rm test2.tmp"));
+    apply_action(
+        Mode::Active,
+        Some(is_applied()),
+        VirtualFile::InMemory(String::from("touch test2.tmp")),
+        vars.clone(),
+        &env,
+    )?;
+    let r = unapply_action(Mode::Active, is_applied(), undo(), vars.clone(), &env)?;
+    assert!(matches!(r, ActionResult::Applied));
+    let r = unapply_action(Mode::Active, is_applied(), undo(), vars, &env)?;
+    assert!(matches!(r, ActionResult::AlreadyUnapplied));
+    Ok(())
+}
+#[test]
+fn test_is_applied_script() -> Result<(), ApplyError> {
+    let manifest = PathBuf::from("example/apply.toml");
+    match is_applied_script(Some("example1".into()), None, &manifest)? {
+        (VirtualFile::FsPath(p), vars) => {
+            assert_eq!(p, PathBuf::from("example/example1-is-applied"));
+            assert!(vars.contains_key("EXAMPLE1_DIR"));
+        }
+        (VirtualFile::InMemory(_), _) => panic!("expected FsPath"),
+    }
+    match is_applied_script(None, Some("true".into()), &manifest)? {
+        (VirtualFile::InMemory(s), _) => assert_eq!(s, "true"),
+        (VirtualFile::FsPath(_), _) => panic!("expected InMemory"),
+    }
+    assert!(is_applied_script(Some("nosuchunit".into()), None, &manifest).is_err());
+    assert!(is_applied_script(None, None, &manifest).is_err());
     Ok(())
 }
 
@@ -72,6 +145,32 @@ fn test_appply() -> Result<(), ApplyError> {
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    format: Format,
+    /// exit 0 unchanged, 2 applied, 3 skipped
+    #[arg(long, global = true)]
+    detailed_exitcodes: bool,
+    /// lines of context in template diffs
+    #[arg(long, global = true, default_value_t = 3)]
+    diff_context: usize,
+    /// what placeholders for vars that are not set become
+    #[arg(long, global = true, value_enum, default_value_t = Undefined::Error)]
+    undefined: Undefined,
+    /// yaml, toml, json or .env vars, later files and --var override earlier ones
+    #[arg(long, global = true)]
+    vars_file: Vec<PathBuf>,
+    /// a var whose value is redacted from all output
+    #[arg(long, global = true, num_args = 2, value_names = ["KEY", "VALUE"])]
+    secret_var: Vec<String>,
+    /// a secret var read from a file, - for stdin or /dev/fd/N
+    #[arg(long, global = true, num_args = 2, value_names = ["KEY", "FILE"])]
+    secret_file: Vec<String>,
+    /// colored output, default auto
+    #[arg(long, global = true, value_enum)]
+    color: Option<Color>,
+    /// template and argument delimiters, default @@ @@
+    #[arg(long, global = true, num_args = 2, value_names = ["LEFT", "RIGHT"])]
+    delims: Option<Vec<String>>,
 }
 
 // https://docs.rs/clap/latest/clap/_derive/index.html#arg-attributes
@@ -89,6 +188,8 @@ enum Commands {
         interactive: bool,
         #[arg(short, long,num_args=0..)]
         var: Vec<String>,
+        #[command(flatten)]
+        env: EnvOptions,
         #[arg(last = true, allow_hyphen_values = true)]
         cmd: Vec<String>,
     },
@@ -105,6 +206,9 @@ enum Commands {
         infile: Option<PathBuf>,
         #[arg(short, long)]
         out: Option<PathBuf>,
+        /// print the vars the template uses instead of writing it
+        #[arg(long)]
+        list_vars: bool,
         #[arg(last = true, allow_hyphen_values = true)]
         data: Option<Vec<String>>,
     },
@@ -115,45 +219,240 @@ enum Commands {
         passive: bool,
         #[clap(short, long)]
         interactive: bool,
-        #[arg(short = 'I', long)]
+        /// unit in --manifest with <name>-apply and <name>-is-applied scripts
+        #[arg(short, long)]
+        name: Option<String>,
+        #[arg(short, long, default_value = "apply.toml")]
+        manifest: PathBuf,
+        #[arg(short = 'I', long, conflicts_with = "name")]
         ifnot: Option<String>,
+        #[arg(short, long, required_unless_present = "name", conflicts_with = "name")]
+        then: Option<String>,
+        #[arg(short, long, num_args=0..)]
+        var: Vec<String>,
+        #[command(flatten)]
+        env: EnvOptions,
+    },
+    /// Make sure a line is in a file, or not
+    Line {
+        #[clap(short, long)]
+        active: bool,
+        #[clap(short, long)]
+        passive: bool,
+        #[clap(short, long)]
+        interactive: bool,
         #[arg(short, long)]
-        then: String,
+        file: PathBuf,
         #[arg(short, long, num_args=0..)]
         var: Vec<String>,
+        #[command(flatten)]
+        line: line::LineArgs,
+    },
+    /// Make sure a path exists with a mode and owner, or does not exist
+    File {
+        #[clap(short, long)]
+        active: bool,
+        #[clap(short, long)]
+        passive: bool,
+        #[clap(short, long)]
+        interactive: bool,
+        #[arg(long)]
+        path: PathBuf,
+        #[command(flatten)]
+        file: file::FileArgs,
+    },
+    /// Make sure a directory tree exists, with modes per level
+    Dir {
+        #[clap(short, long)]
+        active: bool,
+        #[clap(short, long)]
+        passive: bool,
+        #[clap(short, long)]
+        interactive: bool,
+        #[arg(long)]
+        path: PathBuf,
+        #[command(flatten)]
+        dir: dir::DirArgs,
+    },
+    /// Make sure a symlink points to a target
+    Link {
+        #[clap(short, long)]
+        active: bool,
+        #[clap(short, long)]
+        passive: bool,
+        #[clap(short, long)]
+        interactive: bool,
+        #[arg(long)]
+        path: PathBuf,
+        #[command(flatten)]
+        link: link::LinkArgs,
+    },
+    /// Make sure a file, or a directory of files, is a copy of --src
+    Copy {
+        #[clap(short, long)]
+        active: bool,
+        #[clap(short, long)]
+        passive: bool,
+        #[clap(short, long)]
+        interactive: bool,
+        #[command(flatten)]
+        copy: copy::CopyArgs,
     },
+    /// Exit 0 if applied, 1 if not applied
     IsApplied {
+        #[arg(short, long, required_unless_present = "ifnot")]
+        name: Option<String>,
+        #[arg(short, long, conflicts_with = "name")]
+        ifnot: Option<String>,
+        #[arg(short, long, default_value = "apply.toml")]
+        manifest: PathBuf,
+        #[arg(short, long, num_args=0..)]
+        var: Vec<String>,
+        #[command(flatten)]
+        env: EnvOptions,
+    },
+    /// Run --undo (or <name>-unapply) if --is-applied succeeds
+    Unapply {
+        #[clap(short, long)]
+        active: bool,
+        #[clap(short, long)]
+        passive: bool,
+        #[clap(short, long)]
+        interactive: bool,
+        /// unit in --manifest with <name>-unapply and <name>-is-applied scripts
         #[arg(short, long)]
-        name: String,
-        #[arg(short, long)]
-        ifnot: String,
+        name: Option<String>,
+        #[arg(short, long, default_value = "apply.toml")]
+        manifest: PathBuf,
+        #[arg(
+            short = 'I',
+            long,
+            required_unless_present = "name",
+            conflicts_with = "name"
+        )]
+        is_applied: Option<String>,
+        #[arg(short, long, required_unless_present = "name", conflicts_with = "name")]
+        undo: Option<String>,
+        #[arg(short, long, num_args=0..)]
+        var: Vec<String>,
+        #[command(flatten)]
+        env: EnvOptions,
+    },
+    /// Run the steps of a yaml or toml playbook in order
+    Playbook {
+        #[clap(short, long)]
+        active: bool,
+        #[clap(short, long)]
+        passive: bool,
+        #[clap(short, long)]
+        interactive: bool,
+        #[arg(short, long, num_args=0..)]
+        var: Vec<String>,
+        #[command(flatten)]
+        env: EnvOptions,
+        file: PathBuf,
     },
     // Save: save key and value to yaml file filename
     // example: fastidious save --key "key" --value "value" --filename "filename"
     Save {
+        /// dots for nested keys, e.g. db.port
         #[arg(short, long)]
         key: String,
-        #[arg(short, long)]
-        value: String,
-        #[arg(short, long)]
-        filename: String,
+        /// read as yaml, e.g. 5432 or [a, b], unless --string
+        #[arg(short, long, required_unless_present = "delete")]
+        value: Option<String>,
+        /// remove the key instead
+        #[arg(long, conflicts_with_all = ["value", "if_absent"])]
+        delete: bool,
+        /// leave a key that is already set alone
+        #[arg(long)]
+        if_absent: bool,
+        /// keep the value a string
+        #[arg(long)]
+        string: bool,
+        /// with --encrypt, default the vars_store setting
+        #[arg(short, long, required_unless_present = "encrypt")]
+        filename: Option<PathBuf>,
+        /// into an age or gpg encrypted vars store
+        #[arg(long)]
+        encrypt: bool,
+        /// with --encrypt, a passphrase instead of the age identity or gpg key
+        #[arg(long)]
+        passphrase: bool,
+    },
+    /// The encrypted vars store written by save --encrypt
+    Vars {
+        #[command(subcommand)]
+        command: VarsCommand,
+        /// default the vars_store setting
+        #[arg(long)]
+        store: Option<PathBuf>,
+        /// a passphrase instead of the age identity or gpg key
+        #[arg(long)]
+        passphrase: bool,
+    },
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
     },
 }
 
-fn main1() -> Result<ActionResult, ApplyError> {
-    env_logger::init();
+#[derive(Debug, Subcommand)]
+enum VarsCommand {
+    /// print the value of a key
+    Get { key: String },
+    /// print the keys, never the values
+    List,
+    /// remove a key
+    Rm { key: String },
+    /// decrypt into $EDITOR and encrypt again when it exits
+    Edit,
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    /// print the effective settings and where each came from
+    Show,
+}
+
+fn main1(args: Cli) -> Result<ActionResult, ApplyError> {
+    // log lines go through the same redaction as the rest of the output
+    env_logger::Builder::from_default_env()
+        .format(|buf, record| {
+            writeln!(
+                buf,
+                "[{} {}] {}",
+                record.level(),
+                record.target(),
+                secret::redact(&record.args().to_string())
+            )
+        })
+        .init();
     debug!("debug enabled");
     info!("info enabled");
 
     info!("info enabled");
-    let _conf = Config::builder()
-        .add_source(config::Environment::with_prefix("FASTIDIOUS"))
-        .add_source(config::File::with_name("fastidious").required(false))
-        .build()
-        .map_err(ApplyError::ConfigError)?;
+    let mut settings = Settings::load()?;
+    if let Some(delims) = &args.delims {
+        settings.set_delims(&delims[0], &delims[1], "flag --delims");
+    }
+    if let Some(color) = args.color {
+        settings.set_color(color, "flag --color");
+    }
+    configfile::set_settings(settings.clone());
     info!("after conf");
 
-    let args = Cli::parse();
+    passive::set_format(args.format);
+    diff::set_context(args.diff_context);
+    template::set_undefined(args.undefined);
+    template::set_delims(template::Delims::new(
+        &settings.delims.0,
+        &settings.delims.1,
+    )?);
+    let file_vars = merge_vars(
+        merge_vars(settings.vars.clone(), varsfile::load_all(&args.vars_file)?),
+        secret::from_args(args.secret_var, args.secret_file)?,
+    );
 
     match args.command {
         Commands::Dryrun {
@@ -161,30 +460,97 @@ fn main1() -> Result<ActionResult, ApplyError> {
             passive,
             interactive,
             var,
+            env,
             cmd,
         } => {
-            let mode = get_mode(active, passive, interactive);
-            let vars = crate::cmd::to_vars_split_odd(var);
+            let mode = get_mode(active, passive, interactive, settings.mode);
+            let vars = merge_vars(file_vars, crate::cmd::to_vars_split_odd(var));
             debug!("vars {:#?}", vars);
             debug!("cmd {:#?}", cmd);
-            dryrun::dryrun(mode, vars, cmd)
+            dryrun::dryrun(mode, vars, &env, cmd)
         }
         Commands::Apply {
             active,
             interactive,
+            name,
+            manifest,
             ifnot,
             then,
             var,
+            env,
             passive,
         } => {
-            let mode = get_mode(active, passive, interactive);
-            let vars = crate::cmd::to_vars_split_odd(var);
-            apply_action(mode, ifnot, then, vars)
+            let mode = get_mode(active, passive, interactive, settings.mode);
+            let vars = merge_vars(file_vars, crate::cmd::to_vars_split_odd(var));
+            match (name, then) {
+                (Some(name), None) => {
+                    apply_with_prerequisites(mode, &manifest, &name, vars, &env)
+                }
+                (_, Some(then)) => apply_action(
+                    mode,
+                    ifnot.map(VirtualFile::InMemory),
+                    VirtualFile::InMemory(then),
+                    vars,
+                    &env,
+                ),
+                (None, None) => Err(ApplyError::ExpectedArg(String::from("--name or --then"))),
+            }
         }
-        Commands::IsApplied { name, ifnot } => {
+        Commands::IsApplied {
+            name,
+            ifnot,
+            manifest,
+            var,
+            env,
+        } => {
             debug!("maybe_ifnot {:?}", ifnot);
             debug!("name{:?}", name);
-            todo!()
+            let (script, unit_vars) = is_applied_script(name, ifnot, &manifest)?;
+            let cli_vars = merge_vars(file_vars, crate::cmd::to_vars_split_odd(var));
+            let vars = merge_vars(unit_vars, cli_vars);
+            if do_is_applied(vars, &env, &script)? {
+                Ok(ActionResult::AlreadyApplied)
+            } else {
+                Ok(ActionResult::NotApplied)
+            }
+        }
+        Commands::Unapply {
+            active,
+            passive,
+            interactive,
+            name,
+            manifest,
+            is_applied,
+            undo,
+            var,
+            env,
+        } => {
+            let mode = get_mode(active, passive, interactive, settings.mode);
+            let vars = merge_vars(file_vars, crate::cmd::to_vars_split_odd(var));
+            match (name, is_applied, undo) {
+                (_, Some(is_applied), Some(undo)) => unapply_action(
+                    mode,
+                    VirtualFile::InMemory(is_applied),
+                    VirtualFile::InMemory(undo),
+                    vars,
+                    &env,
+                ),
+                (Some(name), _, _) => {
+                    let unit = Manifest::load(&manifest)?.unit(&name)?;
+                    let is_applied_script = unit.script(Action::IsApplied)?;
+                    let undo_script = unit.script(Action::UnApply)?;
+                    unapply_action(
+                        mode,
+                        is_applied_script,
+                        undo_script,
+                        merge_vars(unit.vars, vars),
+                        &env,
+                    )
+                }
+                (None, _, _) => Err(ApplyError::ExpectedArg(String::from(
+                    "--name or --is-applied and --undo",
+                ))),
+            }
         }
         Commands::Template {
             active,
@@ -194,80 +560,274 @@ fn main1() -> Result<ActionResult, ApplyError> {
             data,
             infile,
             out,
+            list_vars,
         } => {
-            let mode = get_mode(active, interactive, passive);
-            let vars = crate::cmd::to_vars_split_odd(var);
+            let mode = get_mode(active, passive, interactive, settings.mode);
+            let vars = merge_vars(file_vars, crate::cmd::to_vars_split_odd(var));
             let str_data = data.map(|v| v.join(" "));
             debug!("str_data {:?}", str_data);
+            if list_vars {
+                return print_vars(&dryrun::template_source(str_data, infile)?);
+            }
             let output_file = match out {
                 Some(of) => DestFile::new(of),
                 None => DestFile::new(PathBuf::from("/dev/stdout")),
             };
-            dryrun::do_template(mode, vars, str_data, infile, output_file).map(ActionResult::from)
+            dryrun::do_template(mode, vars, str_data, infile, output_file)
+        }
+        Commands::Playbook {
+            active,
+            passive,
+            interactive,
+            var,
+            env,
+            file,
+        } => {
+            let mode = get_mode(active, passive, interactive, settings.mode);
+            let vars = merge_vars(file_vars, crate::cmd::to_vars_split_odd(var));
+            let summary = Playbook::load(&file)?.run(mode, &vars, &env)?;
+            summary.print();
+            summary.result()
         }
         Commands::Save {
             key,
             value,
             filename,
-        } => save_key_val(key, value, filename),
+            encrypt: true,
+            passphrase,
+            delete,
+            if_absent,
+            ..
+        } => {
+            let store = store::Store::new(filename, passphrase);
+            match value {
+                Some(value) if !delete => store.set(key, value, if_absent),
+                _ => store.rm(&key),
+            }
+        }
+        Commands::Save {
+            key,
+            value,
+            filename: Some(filename),
+            delete,
+            if_absent,
+            string,
+            ..
+        } => {
+            let change = match value {
+                Some(value) if !delete => {
+                    let value = yamledit::parse_value(&value, string)?;
+                    if if_absent {
+                        yamledit::Change::SetIfAbsent(value)
+                    } else {
+                        yamledit::Change::Set(value)
+                    }
+                }
+                _ => yamledit::Change::Delete,
+            };
+            save_key_val(&key, change, &filename)
+        }
+        Commands::Save { filename: None, .. } => {
+            Err(ApplyError::ExpectedArg(String::from("filename or encrypt")))
+        }
+        Commands::Vars {
+            command,
+            store,
+            passphrase,
+        } => {
+            let store = store::Store::new(store, passphrase);
+            match command {
+                VarsCommand::Get { key } => store.get(&key),
+                VarsCommand::List => store.list(),
+                VarsCommand::Rm { key } => store.rm(&key),
+                VarsCommand::Edit => store.edit(),
+            }
+        }
+        Commands::Line {
+            active,
+            passive,
+            interactive,
+            file,
+            var,
+            mut line,
+        } => {
+            let mode = get_mode(active, passive, interactive, settings.mode);
+            let vars = merge_vars(file_vars, crate::cmd::to_vars_split_odd(var));
+            let fill =
+                |value: Option<String>| value.map(|v| template::render(&vars, &v)).transpose();
+            line.line = fill(line.line)?;
+            line.regex = fill(line.regex)?;
+            line.insert_after = fill(line.insert_after)?;
+            line.insert_before = fill(line.insert_before)?;
+            line::ensure_line(mode, &line.spec()?, &DestFile::new(file))
+        }
+        Commands::File {
+            active,
+            passive,
+            interactive,
+            path,
+            file,
+        } => {
+            let mode = get_mode(active, passive, interactive, settings.mode);
+            file::ensure_file(mode, &path, &file)
+        }
+        Commands::Dir {
+            active,
+            passive,
+            interactive,
+            path,
+            dir,
+        } => {
+            let mode = get_mode(active, passive, interactive, settings.mode);
+            dir::ensure_dir(mode, &path, &dir)
+        }
+        Commands::Link {
+            active,
+            passive,
+            interactive,
+            path,
+            link,
+        } => {
+            let mode = get_mode(active, passive, interactive, settings.mode);
+            link::ensure_link(mode, &path, &link)
+        }
+        Commands::Copy {
+            active,
+            passive,
+            interactive,
+            copy,
+        } => {
+            let mode = get_mode(active, passive, interactive, settings.mode);
+            copy::copy(mode, &copy)
+        }
+        Commands::Config {
+            command: ConfigCommand::Show,
+        } => show_settings(&settings),
     }
 }
-fn load_yaml_to_hashmap(filename: &str) -> Result<HashMap<String, String>, ApplyError> {
-    let contents = std::fs::read_to_string(filename).map_err(ApplyError::IoError)?;
-
-    let yaml: HashMap<String, String> =
-        serde_yaml::from_str(&contents).map_err(ApplyError::YamlReadError)?;
-    Ok(yaml)
+fn show_settings(settings: &Settings) -> Result<ActionResult, ApplyError> {
+    let rows = settings.rows();
+    let width = rows.iter().map(|(key, _, _)| key.len()).max().unwrap_or(0);
+    let value_width = rows
+        .iter()
+        .map(|(_, value, _)| value.len())
+        .max()
+        .unwrap_or(0);
+    for (key, value, source) in rows {
+        passive::text(format!(
+            "{:width$}  {:value_width$}  {}",
+            key,
+            value,
+            source,
+            width = width,
+            value_width = value_width
+        ));
+        passive::emit(Event {
+            action: String::from("setting"),
+            key: Some(key),
+            value: Some(value),
+            source: Some(source),
+            ..Event::default()
+        });
+    }
+    Ok(ActionResult::AlreadyApplied)
 }
-fn save_key_val(key: String, value: String, filename: String) -> Result<ActionResult, ApplyError> {
-    let filepath = Path::new(&filename);
-    if filepath.exists() {
-        let mut yaml = load_yaml_to_hashmap(&filename)?;
-        yaml.insert(key, value);
-        let mut file = std::fs::File::create(filepath)
-            .with_context(|| format!("Error creating {}", filename))?;
-        let yaml =
-            serde_yaml::to_string(&yaml).with_context(|| format!("error parsing {}", filename))?;
-        file.write_all(yaml.as_bytes())
-            .with_context(|| format!("error writing {}", filename))?;
-    } else {
-        let mut file = std::fs::File::create(filepath)?;
-        let mut map = HashMap::new();
-        map.insert(key.clone(), value);
-        let yaml = serde_yaml::to_string(&map)
-            .with_context(|| format!("error adding yaml key {}", key))?;
-        file.write_all(yaml.as_bytes())
-            .with_context(|| format!("error writing yaml {}", filename))?;
+// edits the yaml file in place, see yamledit.rs
+fn save_key_val(
+    key: &str,
+    change: yamledit::Change,
+    filename: &Path,
+) -> Result<ActionResult, ApplyError> {
+    let text = match filename.exists() {
+        true => std::fs::read_to_string(filename)
+            .with_context(|| format!("error reading {}", filename.display()))?,
+        false => String::new(),
+    };
+    match yamledit::save_yaml(&text, key, &change)? {
+        Some(edited) => {
+            std::fs::write(filename, edited)
+                .with_context(|| format!("error writing {}", filename.display()))?;
+            Ok(ActionResult::Applied)
+        }
+        None if matches!(change, yamledit::Change::Delete) => Ok(ActionResult::AlreadyUnapplied),
+        None => Ok(ActionResult::AlreadyApplied),
     }
-    Ok(ActionResult::Applied)
 }
-fn get_mode(active: bool, _passive: bool, interactive: bool) -> files::Mode {
+// the mode flag, otherwise the mode from the settings
+fn get_mode(active: bool, passive: bool, interactive: bool, default: Mode) -> files::Mode {
     if active {
         files::Mode::Active
     } else if interactive {
         files::Mode::Interactive
-    } else {
+    } else if passive {
         files::Mode::Passive
+    } else {
+        default
+    }
+}
+fn print_vars(template: &SrcFile) -> Result<ActionResult, ApplyError> {
+    let names = template::list_vars(&template::read_template(template)?)?;
+    for name in &names {
+        passive::text(name);
+    }
+    passive::emit(Event {
+        action: String::from("list-vars"),
+        template: Some(template.to_string()),
+        vars: Some(names),
+        ..Event::default()
+    });
+    Ok(ActionResult::AlreadyApplied)
+}
+// later vars win: unit table < settings vars < vars files < --var, see README
+fn merge_vars(mut unit_vars: Vars, cli_vars: Vars) -> Vars {
+    unit_vars.extend(cli_vars);
+    unit_vars
+}
+// the unit's requires and befores are applied first, as a playbook of units
+fn apply_with_prerequisites(
+    mode: Mode,
+    manifest: &Path,
+    name: &str,
+    vars: Vars,
+    env: &EnvOptions,
+) -> Result<ActionResult, ApplyError> {
+    let units = Manifest::load(manifest)?.with_prerequisites(name)?;
+    if units.len() == 1 && units[0].requires.is_empty() {
+        return apply_unit(mode, manifest, name, vars, env);
     }
+    let summary = Playbook::from_units(manifest, &units).run(mode, &vars, env)?;
+    summary.print();
+    summary.result()
+}
+fn apply_unit(
+    mode: Mode,
+    manifest: &Path,
+    name: &str,
+    vars: Vars,
+    env: &EnvOptions,
+) -> Result<ActionResult, ApplyError> {
+    let unit = Manifest::load(manifest)?.unit(name)?;
+    let is_applied_script = unit.script(Action::IsApplied).ok();
+    let apply_script = unit.script(Action::Apply)?;
+    apply_action(
+        mode,
+        is_applied_script,
+        apply_script,
+        merge_vars(unit.vars, vars),
+        env,
+    )
 }
 fn apply_action(
     mode: Mode,
-    maybe_ifnot: Option<String>,
-    then: String,
+    maybe_ifnot: Option<VirtualFile>,
+    apply_script: VirtualFile,
     vars: Vars,
+    env: &EnvOptions,
 ) -> Result<ActionResult, ApplyError> {
     debug!("apply_action");
 
-    let _conf = Config::builder()
-        .add_source(config::Environment::with_prefix("FASTIDIOUS"))
-        .add_source(config::File::with_name("fastidious").required(false))
-        .build()?;
-
-    let is = if let Some(ifnot) = maybe_ifnot {
-        let is_applied_script = VirtualFile::InMemory(ifnot);
-        do_is_applied(vars.clone(), &is_applied_script).map_err(|e| {
-            ApplyError::ScriptError(format!("script error {:?} {:?}", is_applied_script, e))
-        })
+    let is = if let Some(is_applied_script) = maybe_ifnot {
+        do_is_applied(vars.clone(), env, &is_applied_script)
     } else {
         Ok(false)
     }?;
@@ -276,37 +836,85 @@ fn apply_action(
         info!("Already applied");
         Ok(ActionResult::AlreadyApplied)
     } else {
-        let apply_script = VirtualFile::InMemory(then);
+        do_apply(vars, env, &apply_script, mode)
+    }
+}
 
-        do_apply(vars, &apply_script, mode)
+fn unapply_action(
+    mode: Mode,
+    is_applied_script: VirtualFile,
+    undo_script: VirtualFile,
+    vars: Vars,
+    env: &EnvOptions,
+) -> Result<ActionResult, ApplyError> {
+    debug!("unapply_action");
+    let is = do_is_applied(vars.clone(), env, &is_applied_script)?;
+    if is {
+        execute_script(Action::UnApply, &undo_script, vars, env, mode)
+    } else {
+        info!("Already unapplied");
+        Ok(ActionResult::AlreadyUnapplied)
     }
 }
 
 fn do_apply(
     name_config: HashMap<String, String>,
+    env: &EnvOptions,
     script_path: &cmd::VirtualFile,
     mode: files::Mode,
 ) -> Result<ActionResult, ApplyError> {
     debug!("do_apply params {:#?} {:?}", name_config, mode);
-    execute_apply(script_path, name_config, mode)
+    execute_script(Action::Apply, script_path, name_config, env, mode)
+}
+
+// inline --ifnot or <script_dir>/<name>-is-applied from the manifest, clap allows one
+fn is_applied_script(
+    maybe_name: Option<String>,
+    maybe_ifnot: Option<String>,
+    manifest: &Path,
+) -> Result<(VirtualFile, Vars), ApplyError> {
+    match (maybe_name, maybe_ifnot) {
+        (None, Some(ifnot)) => Ok((VirtualFile::InMemory(ifnot), Vars::new())),
+        (Some(name), None) => {
+            let unit = Manifest::load(manifest)?.unit(&name)?;
+            Ok((unit.script(Action::IsApplied)?, unit.vars))
+        }
+        _ => Err(ApplyError::ExpectedArg(String::from("--name or --ifnot"))),
+    }
 }
 
 fn do_is_applied(
     vars: HashMap<String, String>,
+    env: &EnvOptions,
     script: &cmd::VirtualFile,
 ) -> Result<bool, ApplyError> {
     debug!("do_is_applied script {:?}", script);
     debug!("do_is_applied params {:#?}", vars);
-    Ok(apply::is_applied(script, vars))
+    apply::is_applied(script, vars, env)
 }
 
 fn main() {
-    let code = match main1() {
-        Ok(_) => 0,
+    let args = Cli::parse();
+    let detailed = args.detailed_exitcodes;
+    let code = match main1(args) {
+        Ok(result) => {
+            passive::emit(Event {
+                action: String::from("result"),
+                result: Some(result),
+                ..Event::default()
+            });
+            result.exit_code(detailed)
+        }
         Err(err) => {
-            eprintln!("error: {:?}", err);
-            -1
+            eprintln!("{}", secret::redact(&format!("error: {:?}", err)));
+            passive::emit(Event {
+                action: String::from("error"),
+                error: Some(err.to_string()),
+                ..Event::default()
+            });
+            err.exit_code()
         }
     };
+    passive::finish();
     std::process::exit(code);
 }
@@ -0,0 +1,152 @@
+use crate::action::Action;
+use crate::applyerr::ApplyError;
+use crate::cmd::{Vars, VirtualFile};
+use crate::configfile::settings;
+use crate::secret;
+use config::Config;
+use log::trace;
+use std::path::{Path, PathBuf};
+
+#[test]
+fn test_manifest() -> Result<(), ApplyError> {
+    let manifest = Manifest::load(Path::new("example/apply.toml"))?;
+    let unit = manifest.unit("example1")?;
+    assert_eq!(unit.vars.get("EXAMPLE1_VALUE").unwrap(), "applied");
+    assert!(unit.script(Action::Apply).is_ok());
+    assert!(unit.script(Action::IsApplied).is_ok());
+    assert!(unit.script(Action::UnApply).is_ok());
+    assert!(manifest.unit("nosuchunit").is_err());
+    Ok(())
+}
+
+// apply.toml: script_dir = "dir" and a [name] table of vars per unit
+pub struct Manifest {
+    conf: Config,
+    script_dir: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct Unit {
+    pub name: String,
+    pub vars: Vars,
+    // units that must be applied before this one
+    pub requires: Vec<String>,
+    // units that must be applied after this one
+    pub before: Vec<String>,
+    apply: Option<PathBuf>,
+    is_applied: Option<PathBuf>,
+    unapply: Option<PathBuf>,
+}
+
+impl Manifest {
+    // a missing manifest is not an error, units are then just scripts in the configured script_dir
+    pub fn load(path: &Path) -> Result<Self, ApplyError> {
+        trace!("Manifest::load {:?}", path);
+        let conf = Config::builder()
+            .add_source(config::File::from(path).required(false))
+            .build()?;
+        let script_dir = match conf.get_string("script_dir") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => settings().script_dir.clone(),
+        };
+        Ok(Self { conf, script_dir })
+    }
+    pub fn unit(&self, name: &str) -> Result<Unit, ApplyError> {
+        let mut vars = Vars::new();
+        let mut requires = Vec::new();
+        let mut before = Vec::new();
+        if let Ok(table) = self.conf.get_table(name) {
+            for (key, value) in table {
+                match key.as_str() {
+                    "requires" => requires = unit_names(value)?,
+                    "before" => before = unit_names(value)?,
+                    _ => {
+                        vars.insert(key, secret::reveal(value.into_string()?));
+                    }
+                }
+            }
+        }
+        let unit = Unit {
+            name: name.to_owned(),
+            vars,
+            requires,
+            before,
+            apply: self.find_script(name, Action::Apply),
+            is_applied: self.find_script(name, Action::IsApplied),
+            unapply: self.find_script(name, Action::UnApply),
+        };
+        debug!("unit {:?}", unit);
+        if unit.apply.is_none() && unit.is_applied.is_none() && unit.unapply.is_none() {
+            Err(ApplyError::NameNotFound(format!(
+                "{} (no scripts in {:?})",
+                name, self.script_dir
+            )))
+        } else {
+            Ok(unit)
+        }
+    }
+    // the unit, what it requires and what is declared before any of them
+    pub fn with_prerequisites(&self, name: &str) -> Result<Vec<Unit>, ApplyError> {
+        let mut declared_before: Vec<(String, Vec<String>)> = Vec::new();
+        for (key, value) in self.conf.cache.clone().into_table()? {
+            if let Ok(mut table) = value.into_table() {
+                if let Some(before) = table.remove("before") {
+                    declared_before.push((key, unit_names(before)?));
+                }
+            }
+        }
+        declared_before.sort();
+        let mut units: Vec<Unit> = Vec::new();
+        let mut todo = vec![name.to_owned()];
+        while let Some(next) = todo.pop() {
+            if units.iter().any(|unit| unit.name == next) {
+                continue;
+            }
+            let unit = self.unit(&next)?;
+            todo.extend(unit.requires.iter().rev().cloned());
+            for (other, before) in &declared_before {
+                if before.contains(&next) {
+                    todo.push(other.clone());
+                }
+            }
+            units.push(unit);
+        }
+        Ok(units)
+    }
+    fn find_script(&self, name: &str, action: Action) -> Option<PathBuf> {
+        let path = self
+            .script_dir
+            .join(format!("{}-{}", name, action.script_suffix()));
+        if path.is_file() {
+            Some(path)
+        } else {
+            None
+        }
+    }
+}
+
+impl Unit {
+    pub fn script(&self, action: Action) -> Result<VirtualFile, ApplyError> {
+        let found = match action {
+            Action::Apply => &self.apply,
+            Action::IsApplied => &self.is_applied,
+            Action::UnApply => &self.unapply,
+        };
+        match found {
+            Some(path) => Ok(VirtualFile::FsPath(path.clone())),
+            None => Err(ApplyError::NameNotFound(format!(
+                "{}-{}",
+                self.name,
+                action.script_suffix()
+            ))),
+        }
+    }
+}
+// requires = ["dir", ...] in a unit's table
+fn unit_names(value: config::Value) -> Result<Vec<String>, ApplyError> {
+    Ok(value
+        .into_array()?
+        .into_iter()
+        .map(|name| name.into_string())
+        .collect::<Result<_, _>>()?)
+}
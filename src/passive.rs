@@ -1,11 +1,150 @@
 use ansi_term::Colour;
 use config::ConfigError;
+use configfile::{self, Color};
+use dryrun::ActionResult;
+use secret;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
 use std::fmt::{Debug, Display};
+use std::io::{self, IsTerminal};
 use std::path::{Path, PathBuf};
-use std::{ffi::OsString, fmt};
+use std::sync::{Mutex, OnceLock};
+use std::{env, ffi::OsString, fmt};
 use thiserror::Error;
 
-#[derive(Debug, Copy, Clone)]
+#[test]
+fn test_event_json() {
+    let event = Event {
+        verb: Some(Verb::Would),
+        action: String::from("run"),
+        script: Some(String::from("true")),
+        result: Some(ActionResult::Skipped),
+        ..Event::default()
+    };
+    let json = serde_json::to_string(&event).unwrap();
+    assert!(json.contains(r#""verb":"Would""#));
+    assert!(json.contains(r#""result":"Skipped""#));
+    assert_eq!(strip_color(&Colour::Green.paint("ok").to_string()), "ok");
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    Text,
+    /// a json array of events when done
+    Json,
+    /// one json event per line as it happens
+    Jsonl,
+}
+
+static FORMAT: OnceLock<Format> = OnceLock::new();
+static EVENTS: Mutex<Vec<serde_json::Value>> = Mutex::new(Vec::new());
+
+// one action as reported by --format json/jsonl
+#[derive(Debug, Default, Serialize)]
+pub struct Event {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verb: Option<Verb>,
+    pub action: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub script: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dest: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<ActionResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stdout: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub counts: Option<BTreeMap<&'static str, usize>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vars: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+pub fn set_format(format: Format) {
+    let _ = FORMAT.set(format);
+}
+pub fn is_text() -> bool {
+    matches!(FORMAT.get(), None | Some(Format::Text))
+}
+// human readable output, dropped when the output is json
+pub fn text<T: Display>(line: T) {
+    if is_text() {
+        let line = secret::redact(&line.to_string());
+        if use_color() {
+            println!("{}", line);
+        } else {
+            println!("{}", strip_color(&line));
+        }
+    }
+}
+
+static COLOR: OnceLock<bool> = OnceLock::new();
+
+// color setting, auto is a terminal without NO_COLOR
+pub fn use_color() -> bool {
+    *COLOR.get_or_init(|| match configfile::settings().color {
+        Color::Always => true,
+        Color::Never => false,
+        Color::Auto => io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none(),
+    })
+}
+// drops ansi escape sequences
+pub fn strip_color(line: &str) -> String {
+    let mut plain = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+pub fn emit(event: Event) {
+    if is_text() {
+        return;
+    }
+    let mut json = serde_json::to_value(&event).expect("event to json");
+    secret::redact_json(&mut json);
+    match FORMAT.get() {
+        Some(Format::Jsonl) => println!("{}", json),
+        _ => EVENTS.lock().expect("events lock").push(json),
+    }
+}
+// prints the collected events for --format json
+pub fn finish() {
+    if let Some(Format::Json) = FORMAT.get() {
+        let events = EVENTS.lock().expect("events lock");
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&*events).expect("events to json")
+        );
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
 pub enum Verb {
     Would,
     Live,
@@ -26,19 +165,31 @@ pub fn color_from_verb(verb: Verb) -> Colour {
 }
 pub fn log_cmd_action(action: &'static str, verb: Verb, cli: String) {
     let color: Colour = color_from_verb(verb);
-    println!(
+    text(format!(
         "{}: {}: {}",
         color.paint(verb.to_string()),
         color.paint(action),
-        color.paint(cli),
-    );
+        color.paint(cli.as_str()),
+    ));
+    emit(Event {
+        verb: Some(verb),
+        action: action.to_owned(),
+        script: Some(cli),
+        ..Event::default()
+    });
 }
-pub fn log_path_action(action: &'static str, verb: Verb, path: &Path) {
+pub fn log_path_action(action: &str, verb: Verb, path: &Path) {
     let color: Colour = color_from_verb(verb);
-    println!(
+    text(format!(
         "{}: {}: {}",
         color.paint(verb.to_string()),
         color.paint(action),
         color.paint(path.display().to_string()),
-    );
+    ));
+    emit(Event {
+        verb: Some(verb),
+        action: action.to_owned(),
+        dest: Some(path.display().to_string()),
+        ..Event::default()
+    });
 }
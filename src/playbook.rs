@@ -0,0 +1,413 @@
+use ansi_term::Colour::{Green, Red, Yellow};
+use applyerr::ApplyError;
+use cmd::{EnvOptions, Vars, VirtualFile};
+use config::Config;
+use dryrun::{self, ActionResult};
+use files::{DestFile, Mode};
+use manifest::{Manifest, Unit};
+use passive::{self, Event, Verb};
+use secret;
+use serde_derive::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+#[test]
+fn test_playbook() -> Result<(), ApplyError> {
+    let playbook = Playbook::load(Path::new("example/playbook.yaml"))?;
+    assert_eq!(playbook.steps.len(), 4);
+    assert_eq!(playbook.steps[2].name, "greeting file");
+    let vars = playbook.step_vars(&playbook.steps[2], &Vars::new());
+    assert_eq!(vars.get("greeting").unwrap(), "hi");
+    let summary = playbook.run(Mode::Passive, &Vars::new(), &EnvOptions::default())?;
+    assert_eq!(summary.failed, 0);
+    Ok(())
+}
+#[test]
+fn test_run_order() -> Result<(), ApplyError> {
+    fn step(name: &str, requires: &[&str], before: &[&str]) -> Step {
+        Step {
+            name: name.into(),
+            vars: Vars::new(),
+            requires: requires.iter().map(|s| s.to_string()).collect(),
+            before: before.iter().map(|s| s.to_string()).collect(),
+            action: StepAction::Dryrun(vec!["true".into()]),
+        }
+    }
+    let mut playbook = Playbook {
+        vars: Vars::new(),
+        manifest: None,
+        steps: vec![
+            step("config", &["dir"], &[]),
+            step("dir", &[], &[]),
+            step("parent", &[], &["dir"]),
+        ],
+    };
+    let deps = playbook.dependencies()?;
+    assert_eq!(run_order(&playbook.steps, &deps)?, vec![2, 1, 0]);
+    playbook.steps.push(step("loop", &["config"], &["parent"]));
+    let deps = playbook.dependencies()?;
+    assert!(matches!(
+        run_order(&playbook.steps, &deps),
+        Err(ApplyError::DependencyCycle(_))
+    ));
+    Ok(())
+}
+#[test]
+fn test_unit_order() -> Result<(), ApplyError> {
+    let dir = std::env::temp_dir().join("test_unit_order");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir(&dir)?;
+    for (name, script) in [("config", "true"), ("dir", "exit 1"), ("parent", "true")] {
+        let path = dir.join(format!("{}-apply", name));
+        fs::write(&path, format!("#!/bin/sh\n{}\n", script))?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+    }
+    let manifest = dir.join("apply.toml");
+    let write = |dir_requires: &str| {
+        fs::write(
+            &manifest,
+            format!(
+                "script_dir = {:?}\n[config]\nrequires = [\"dir\"]\n[dir]\nrequires = [{}]\n[parent]\nbefore = [\"dir\"]\n",
+                dir, dir_requires
+            ),
+        )
+    };
+    write("")?;
+    let units = Manifest::load(&manifest)?.with_prerequisites("config")?;
+    let playbook = Playbook::from_units(&manifest, &units);
+    let order: Vec<&str> = run_order(&playbook.steps, &playbook.dependencies()?)?
+        .into_iter()
+        .map(|i| playbook.steps[i].name.as_str())
+        .collect();
+    assert_eq!(order, vec!["parent", "dir", "config"]);
+    // dir fails, so config is skipped
+    let summary = playbook.run(Mode::Active, &Vars::new(), &EnvOptions::default())?;
+    assert_eq!(
+        (summary.applied, summary.failed, summary.skipped),
+        (1, 1, 1)
+    );
+    write("\"config\"")?;
+    let units = Manifest::load(&manifest)?.with_prerequisites("config")?;
+    let playbook = Playbook::from_units(&manifest, &units);
+    assert!(matches!(
+        playbook
+            .dependencies()
+            .and_then(|deps| run_order(&playbook.steps, &deps)),
+        Err(ApplyError::DependencyCycle(_))
+    ));
+    fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+// a yaml or toml file: global vars and a list of steps run in order
+#[derive(Debug, Deserialize)]
+pub struct Playbook {
+    #[serde(default)]
+    pub vars: Vars,
+    // for unit steps, defaults to apply.toml
+    pub manifest: Option<PathBuf>,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Step {
+    pub name: String,
+    #[serde(default)]
+    pub vars: Vars,
+    // steps that must run before this one
+    #[serde(default)]
+    pub requires: Vec<String>,
+    // steps that must run after this one
+    #[serde(default)]
+    pub before: Vec<String>,
+    #[serde(flatten)]
+    pub action: StepAction,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepAction {
+    Apply {
+        ifnot: Option<String>,
+        then: String,
+    },
+    Template {
+        infile: Option<PathBuf>,
+        data: Option<String>,
+        out: PathBuf,
+    },
+    Dryrun(Vec<String>),
+    Unit(String),
+}
+
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub applied: usize,
+    pub already_applied: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    rows: Vec<(String, String)>,
+}
+
+impl Playbook {
+    pub fn load(path: &Path) -> Result<Self, ApplyError> {
+        let conf = Config::builder()
+            .add_source(config::File::from(path))
+            .build()?;
+        Ok(conf.try_deserialize()?)
+    }
+    // a unit step for each unit, ordered by the units' requires and before
+    pub fn from_units(manifest: &Path, units: &[Unit]) -> Playbook {
+        let known = |names: &[String]| -> Vec<String> {
+            names
+                .iter()
+                .filter(|name| units.iter().any(|unit| &unit.name == *name))
+                .cloned()
+                .collect()
+        };
+        Playbook {
+            vars: Vars::new(),
+            manifest: Some(manifest.to_owned()),
+            steps: units
+                .iter()
+                .map(|unit| Step {
+                    name: unit.name.clone(),
+                    vars: Vars::new(),
+                    requires: unit.requires.clone(),
+                    before: known(&unit.before),
+                    action: StepAction::Unit(unit.name.clone()),
+                })
+                .collect(),
+        }
+    }
+    // global vars < step vars < --var
+    pub fn step_vars(&self, step: &Step, cli_vars: &Vars) -> Vars {
+        let mut vars = self.vars.clone();
+        vars.extend(step.vars.clone());
+        vars.extend(cli_vars.clone());
+        secret::reveal_all(vars)
+    }
+    // prerequisites of each step by index, from both requires and before
+    pub fn dependencies(&self) -> Result<Vec<Vec<usize>>, ApplyError> {
+        let mut index: HashMap<&str, usize> = HashMap::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            if index.insert(step.name.as_str(), i).is_some() {
+                return Err(ApplyError::Error(format!("duplicate step {}", step.name)));
+            }
+        }
+        let lookup = |name: &String| {
+            index
+                .get(name.as_str())
+                .cloned()
+                .ok_or_else(|| ApplyError::NameNotFound(name.clone()))
+        };
+        let mut deps: Vec<Vec<usize>> = vec![Vec::new(); self.steps.len()];
+        for (i, step) in self.steps.iter().enumerate() {
+            for name in &step.requires {
+                deps[i].push(lookup(name)?);
+            }
+            for name in &step.before {
+                deps[lookup(name)?].push(i);
+            }
+        }
+        Ok(deps)
+    }
+    // a step is skipped if a prerequisite failed or, in Interactive mode, was skipped
+    pub fn run(
+        &self,
+        mode: Mode,
+        cli_vars: &Vars,
+        env: &EnvOptions,
+    ) -> Result<Summary, ApplyError> {
+        let deps = self.dependencies()?;
+        let order = run_order(&self.steps, &deps)?;
+        let manifest = self
+            .manifest
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("apply.toml"));
+        let mut summary = Summary::default();
+        let mut ok: Vec<bool> = vec![false; self.steps.len()];
+        for i in order {
+            let step = &self.steps[i];
+            if let Some(&d) = deps[i].iter().find(|&&d| !ok[d]) {
+                summary.blocked(&step.name, &self.steps[d].name);
+                continue;
+            }
+            passive::text(format!(
+                "{} {}",
+                Green.paint("STEP:"),
+                Green.paint(step.name.as_str())
+            ));
+            let vars = self.step_vars(step, cli_vars);
+            let result = run_step(mode, &manifest, &step.action, vars, env);
+            ok[i] = match (&result, mode) {
+                (Ok(ActionResult::Skipped), Mode::Interactive) => false,
+                (Ok(_), _) => true,
+                (Err(_), _) => false,
+            };
+            summary.add(&step.name, result);
+        }
+        Ok(summary)
+    }
+}
+
+// topological sort, otherwise keeping the order of the file
+fn run_order(steps: &[Step], deps: &[Vec<usize>]) -> Result<Vec<usize>, ApplyError> {
+    let mut done = vec![false; steps.len()];
+    let mut order = Vec::new();
+    while order.len() < steps.len() {
+        match (0..steps.len()).find(|&i| !done[i] && deps[i].iter().all(|&d| done[d])) {
+            Some(i) => {
+                done[i] = true;
+                order.push(i);
+            }
+            None => {
+                let cycle: Vec<&str> = (0..steps.len())
+                    .filter(|&i| !done[i])
+                    .map(|i| steps[i].name.as_str())
+                    .collect();
+                return Err(ApplyError::DependencyCycle(cycle.join(", ")));
+            }
+        }
+    }
+    debug!("run_order {:?}", order);
+    Ok(order)
+}
+
+fn run_step(
+    mode: Mode,
+    manifest: &Path,
+    action: &StepAction,
+    vars: Vars,
+    env: &EnvOptions,
+) -> Result<ActionResult, ApplyError> {
+    debug!("run_step {:?}", action);
+    match action {
+        StepAction::Apply { ifnot, then } => crate::apply_action(
+            mode,
+            ifnot.clone().map(VirtualFile::InMemory),
+            VirtualFile::InMemory(then.clone()),
+            vars,
+            env,
+        ),
+        StepAction::Template { infile, data, out } => {
+            if infile.is_none() && data.is_none() {
+                return Err(ApplyError::ExpectedArg(String::from("infile or data")));
+            }
+            dryrun::do_template(
+                mode,
+                vars,
+                data.clone(),
+                infile.clone(),
+                DestFile::new(out.clone()),
+            )
+        }
+        StepAction::Dryrun(cmd) => {
+            if cmd.is_empty() {
+                return Err(ApplyError::ExpectedArg(String::from("dryrun command")));
+            }
+            dryrun::dryrun(mode, vars, env, cmd.clone())
+        }
+        StepAction::Unit(name) => crate::apply_unit(mode, manifest, name, vars, env),
+    }
+}
+
+impl Summary {
+    pub fn add(&mut self, name: &str, result: Result<ActionResult, ApplyError>) {
+        passive::emit(Event {
+            action: String::from("step"),
+            step: Some(name.to_owned()),
+            result: result.as_ref().ok().cloned(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            ..Event::default()
+        });
+        let status = match result {
+            Ok(ActionResult::Applied) => {
+                self.applied += 1;
+                String::from("Applied")
+            }
+            Ok(ActionResult::AlreadyApplied) | Ok(ActionResult::AlreadyUnapplied) => {
+                self.already_applied += 1;
+                String::from("AlreadyApplied")
+            }
+            Ok(ActionResult::Skipped) | Ok(ActionResult::NotApplied) => {
+                self.skipped += 1;
+                String::from("Skipped")
+            }
+            Err(e) => {
+                passive::text(format!(
+                    "{} {}",
+                    Red.paint("FAILED:"),
+                    Red.paint(e.to_string())
+                ));
+                self.failed += 1;
+                format!("Failed: {}", e)
+            }
+        };
+        self.rows.push((name.to_owned(), status));
+    }
+    pub fn blocked(&mut self, name: &str, prerequisite: &str) {
+        passive::text(format!(
+            "{} {} (requires {})",
+            Yellow.paint("SKIP:"),
+            Yellow.paint(name),
+            prerequisite
+        ));
+        passive::emit(Event {
+            verb: Some(Verb::Skipped),
+            action: String::from("step"),
+            step: Some(name.to_owned()),
+            result: Some(ActionResult::Skipped),
+            error: Some(format!("requires {}", prerequisite)),
+            ..Event::default()
+        });
+        self.skipped += 1;
+        self.rows.push((
+            name.to_owned(),
+            format!("Skipped: requires {}", prerequisite),
+        ));
+    }
+    pub fn print(&self) {
+        let mut counts = BTreeMap::new();
+        counts.insert("applied", self.applied);
+        counts.insert("already_applied", self.already_applied);
+        counts.insert("skipped", self.skipped);
+        counts.insert("failed", self.failed);
+        passive::emit(Event {
+            action: String::from("summary"),
+            counts: Some(counts),
+            ..Event::default()
+        });
+        if !passive::is_text() {
+            return;
+        }
+        let width = self.rows.iter().map(|(n, _)| n.len()).max().unwrap_or(0);
+        for (name, status) in &self.rows {
+            passive::text(format!("{:width$}  {}", name, status, width = width));
+        }
+        passive::text(format!(
+            "{} {}  {} {}  {} {}  {} {}",
+            Green.paint("Applied:"),
+            self.applied,
+            Green.paint("AlreadyApplied:"),
+            self.already_applied,
+            Yellow.paint("Skipped:"),
+            self.skipped,
+            Red.paint("Failed:"),
+            self.failed
+        ));
+    }
+    pub fn result(&self) -> Result<ActionResult, ApplyError> {
+        if self.failed > 0 {
+            Err(ApplyError::StepsFailed(self.failed))
+        } else if self.applied > 0 {
+            Ok(ActionResult::Applied)
+        } else if self.skipped > 0 {
+            Ok(ActionResult::Skipped)
+        } else {
+            Ok(ActionResult::AlreadyApplied)
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use applyerr::ApplyError;
+use cmd::Vars;
+use configfile::settings;
+use secret;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use store;
+
+#[test]
+fn test_resolve_vars() -> Result<(), ApplyError> {
+    std::env::set_var("FASTIDIOUS_TEST_RESOLVER", "from env");
+    let path = std::env::temp_dir().join("test_resolver.txt");
+    std::fs::write(&path, "from file\n")?;
+    let mut vars = Vars::new();
+    vars.insert("a".into(), "$env:FASTIDIOUS_TEST_RESOLVER".into());
+    vars.insert("b".into(), format!("$file:{}", path.display()));
+    vars.insert("c".into(), "$cmd:echo from cmd".into());
+    vars.insert("unused".into(), "$cmd:exit 1".into());
+    vars.insert("plain".into(), "cmd:echo plain".into());
+    vars.insert("escaped".into(), "$$env:HOME".into());
+    let names: Vec<String> = vec![
+        "a".into(),
+        "b".into(),
+        "c".into(),
+        "plain".into(),
+        "escaped".into(),
+    ];
+    let resolved = resolve_vars(&vars, &names)?;
+    assert_eq!(resolved.get("a").unwrap(), "from env");
+    assert_eq!(resolved.get("b").unwrap(), "from file");
+    assert_eq!(resolved.get("c").unwrap(), "from cmd");
+    assert_eq!(resolved.get("plain").unwrap(), "cmd:echo plain");
+    assert_eq!(resolved.get("escaped").unwrap(), "$env:HOME");
+    assert!(!resolved.contains_key("unused"));
+    assert_eq!(secret::redact("from cmd"), secret::MASK);
+    assert_eq!(display("$cmd:exit 1"), secret::MASK);
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+// a reference starts with $, $$ starts a value that only looks like one
+pub const SIGIL: char = '$';
+
+// a var value naming where the real value is, looked up on first use
+#[derive(Debug, PartialEq)]
+pub enum Resolver {
+    // $file:/path, one trailing newline dropped
+    File(PathBuf),
+    // $env:NAME
+    Env(String),
+    // $cmd:pass show db, run with the configured shell
+    Cmd(String),
+    // $age:/path/vars.yaml.age#key or $gpg:/path/vars.yaml.gpg#key, the key defaults to the var's
+    Age(PathBuf, Option<String>),
+    Gpg(PathBuf, Option<String>),
+}
+
+impl Resolver {
+    pub fn parse(value: &str) -> Option<Resolver> {
+        let (scheme, rest) = value.strip_prefix(SIGIL)?.split_once(':')?;
+        let encrypted = || match rest.split_once('#') {
+            Some((path, key)) => (PathBuf::from(path), Some(key.to_owned())),
+            None => (PathBuf::from(rest), None),
+        };
+        match scheme {
+            "file" => Some(Resolver::File(PathBuf::from(rest))),
+            "env" => Some(Resolver::Env(rest.to_owned())),
+            "cmd" => Some(Resolver::Cmd(rest.to_owned())),
+            "age" => {
+                let (path, key) = encrypted();
+                Some(Resolver::Age(path, key))
+            }
+            "gpg" => {
+                let (path, key) = encrypted();
+                Some(Resolver::Gpg(path, key))
+            }
+            _ => None,
+        }
+    }
+    fn resolve(&self, name: &str) -> Result<String, ApplyError> {
+        match self {
+            Resolver::File(path) => secret::read(path),
+            Resolver::Env(var) => std::env::var(var)
+                .map_err(|_| ApplyError::VarNotFound(format!("{} from env:{}", name, var))),
+            Resolver::Cmd(script) => run(script),
+            Resolver::Age(path, key) | Resolver::Gpg(path, key) => {
+                let key = key.as_deref().unwrap_or(name);
+                decrypted(path)?.get(key).cloned().ok_or_else(|| {
+                    ApplyError::VarNotFound(format!("{} in {}", key, path.display()))
+                })
+            }
+        }
+    }
+}
+
+// references already resolved, a cmd or decryption runs once per process
+static RESOLVED: Mutex<Option<HashMap<String, String>>> = Mutex::new(None);
+static DECRYPTED: Mutex<Option<HashMap<PathBuf, Vars>>> = Mutex::new(None);
+
+// vars with the references among names resolved, and every other reference left out
+pub fn resolve_vars(vars: &Vars, names: &[String]) -> Result<Vars, ApplyError> {
+    let mut resolved = Vars::new();
+    for (name, value) in vars {
+        match Resolver::parse(value) {
+            None => {
+                resolved.insert(name.clone(), unescape(value).to_owned());
+            }
+            Some(_) if !names.contains(name) => (),
+            Some(resolver) => {
+                resolved.insert(name.clone(), cached(name, value, &resolver)?);
+            }
+        }
+    }
+    Ok(resolved)
+}
+// "$$env:HOME" is the literal "$env:HOME"
+pub fn unescape(value: &str) -> &str {
+    match value.strip_prefix(SIGIL) {
+        Some(rest) if Resolver::parse(rest).is_some() => rest,
+        _ => value,
+    }
+}
+// a reference is printed as the secret it stands for, before it is looked up
+pub fn display(value: &str) -> &str {
+    if Resolver::parse(value).is_some() {
+        secret::MASK
+    } else {
+        unescape(value)
+    }
+}
+fn cached(name: &str, value: &str, resolver: &Resolver) -> Result<String, ApplyError> {
+    let key = format!("{}={}", name, value);
+    if let Some(found) = RESOLVED
+        .lock()
+        .expect("resolved lock")
+        .as_ref()
+        .and_then(|cache| cache.get(&key))
+    {
+        return Ok(found.clone());
+    }
+    debug!("resolve {} from {:?}", name, resolver);
+    let found = resolver.resolve(name)?;
+    secret::add(&found);
+    RESOLVED
+        .lock()
+        .expect("resolved lock")
+        .get_or_insert_with(HashMap::new)
+        .insert(key, found.clone());
+    Ok(found)
+}
+// stdout without the trailing newline, stdin and stderr stay on the terminal for prompts
+fn run(script: &str) -> Result<String, ApplyError> {
+    let output = Command::new(&settings().shell)
+        .arg("-c")
+        .arg(script)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| ApplyError::ExecError(format!("cmd:{} {:?}", script, e)))?;
+    if !output.status.success() {
+        return Err(ApplyError::ExecError(format!(
+            "cmd:{} exited with {}",
+            script, output.status
+        )));
+    }
+    let mut value = String::from_utf8_lossy(&output.stdout).into_owned();
+    if value.ends_with('\n') {
+        value.pop();
+    }
+    Ok(value)
+}
+
+// a vars file decrypted with age or gpg, parsed by the extension under .age/.gpg
+fn decrypted(path: &Path) -> Result<Vars, ApplyError> {
+    if let Some(vars) = DECRYPTED
+        .lock()
+        .expect("decrypted lock")
+        .as_ref()
+        .and_then(|cache| cache.get(path))
+    {
+        return Ok(vars.clone());
+    }
+    let vars = store::load(path)?;
+    for value in vars.values() {
+        secret::add(value);
+    }
+    DECRYPTED
+        .lock()
+        .expect("decrypted lock")
+        .get_or_insert_with(HashMap::new)
+        .insert(path.to_owned(), vars.clone());
+    Ok(vars)
+}
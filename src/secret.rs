@@ -0,0 +1,109 @@
+use applyerr::ApplyError;
+use cmd::{to_vars_split_odd, Vars};
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Mutex;
+
+#[test]
+fn test_redact() {
+    assert_eq!(reveal(String::from("secret:hunter2")), "hunter2");
+    assert_eq!(reveal(String::from("plain")), "plain");
+    add("s3cr3t-token");
+    assert_eq!(
+        redact("curl -H \"Bearer s3cr3t-token\" hunter2"),
+        "curl -H \"Bearer ***\" ***"
+    );
+    let mut value = serde_json::json!({"script": "echo s3cr3t-token", "exit_code": 0});
+    redact_json(&mut value);
+    assert_eq!(value["script"], "echo ***");
+    add("on");
+    assert_eq!(redact("done on one"), "done on one");
+}
+
+pub const PREFIX: &str = "secret:";
+pub const MASK: &str = "***";
+// shorter values would mask the same few characters everywhere
+pub const MIN_LEN: usize = 4;
+
+// values to hide, longest first so a secret containing another is masked whole
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+pub fn add(value: &str) {
+    if value.is_empty() {
+        return;
+    }
+    if value.chars().count() < MIN_LEN {
+        warn!(
+            "a secret shorter than {} characters is not redacted from output",
+            MIN_LEN
+        );
+        return;
+    }
+    let mut secrets = SECRETS.lock().expect("secrets lock");
+    if !secrets.iter().any(|s| s == value) {
+        secrets.push(value.to_owned());
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+    }
+}
+// a value from a vars file, "secret:hunter2" is the secret hunter2
+pub fn reveal(value: String) -> String {
+    match value.strip_prefix(PREFIX) {
+        Some(secret) => {
+            add(secret);
+            secret.to_owned()
+        }
+        None => value,
+    }
+}
+pub fn reveal_all(vars: Vars) -> Vars {
+    vars.into_iter().map(|(k, v)| (k, reveal(v))).collect()
+}
+// every secret value replaced by ***
+pub fn redact(text: &str) -> String {
+    let secrets = SECRETS.lock().expect("secrets lock");
+    let mut text = text.to_owned();
+    for secret in secrets.iter() {
+        if text.contains(secret.as_str()) {
+            text = text.replace(secret.as_str(), MASK);
+        }
+    }
+    text
+}
+// redacts the strings of a json event, so escaping does not hide a secret
+pub fn redact_json(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::String(s) => *s = redact(s),
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact_json),
+        serde_json::Value::Object(map) => map.values_mut().for_each(redact_json),
+        _ => (),
+    }
+}
+
+// --secret-var KEY VALUE and --secret-file KEY FILE, where FILE - is stdin
+pub fn from_args(values: Vec<String>, files: Vec<String>) -> Result<Vars, ApplyError> {
+    let mut vars = to_vars_split_odd(values);
+    for (key, path) in to_vars_split_odd(files) {
+        vars.insert(key, read(Path::new(&path))?);
+    }
+    for value in vars.values() {
+        add(value);
+    }
+    Ok(vars)
+}
+// one trailing newline is dropped, as written by echo
+pub fn read(path: &Path) -> Result<String, ApplyError> {
+    let mut value = String::new();
+    if path == Path::new("-") {
+        io::stdin().read_to_string(&mut value)?;
+    } else {
+        value = std::fs::read_to_string(path)
+            .map_err(|e| ApplyError::Error(format!("secret file {:?}: {}", path, e)))?;
+    }
+    if value.ends_with('\n') {
+        value.pop();
+        if value.ends_with('\r') {
+            value.pop();
+        }
+    }
+    Ok(value)
+}
@@ -0,0 +1,240 @@
+use applyerr::ApplyError;
+use cmd::Vars;
+use configfile::settings;
+use dryrun::ActionResult;
+use passive::{self, Event};
+use std::collections::BTreeMap;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use varsfile;
+
+#[test]
+fn test_store() -> Result<(), ApplyError> {
+    let missing = std::env::temp_dir().join("test_store_missing.yaml.age");
+    assert!(load(&missing)?.is_empty());
+    let header = std::env::temp_dir().join("test_store_header.age");
+    fs::write(
+        &header,
+        "age-encryption.org/v1\n-> scrypt abc 18\nxyz\n--- mac\n",
+    )?;
+    assert!(is_passphrase(&header));
+    assert!(is_gpg(Path::new("vars.yaml.gpg")));
+    assert!(!is_gpg(Path::new("vars.yaml.age")));
+    fs::remove_file(header)?;
+    Ok(())
+}
+
+// an age or gpg encrypted yaml file of vars, see "Encrypted vars" in README.md
+pub struct Store {
+    path: PathBuf,
+    // encrypt with a passphrase instead of the age identity or gpg key
+    passphrase: bool,
+}
+
+impl Store {
+    // the vars_store setting unless a path is given
+    pub fn new(path: Option<PathBuf>, passphrase: bool) -> Self {
+        let path = path.unwrap_or_else(|| settings().vars_store.clone());
+        // an existing age file keeps how it was encrypted
+        let passphrase = passphrase || (!is_gpg(&path) && is_passphrase(&path));
+        Self { path, passphrase }
+    }
+    pub fn get(&self, key: &str) -> Result<ActionResult, ApplyError> {
+        let vars = load(&self.path)?;
+        let value = vars.get(key).ok_or_else(|| {
+            ApplyError::VarNotFound(format!("{} in {}", key, self.path.display()))
+        })?;
+        // the value is the output, it is not redacted
+        if passive::is_text() {
+            println!("{}", value);
+        }
+        passive::emit(Event {
+            action: String::from("vars get"),
+            key: Some(key.to_owned()),
+            value: Some(value.clone()),
+            ..Event::default()
+        });
+        Ok(ActionResult::AlreadyApplied)
+    }
+    pub fn list(&self) -> Result<ActionResult, ApplyError> {
+        let mut keys: Vec<String> = load(&self.path)?.into_keys().collect();
+        keys.sort();
+        for key in &keys {
+            passive::text(key);
+        }
+        passive::emit(Event {
+            action: String::from("vars list"),
+            vars: Some(keys),
+            ..Event::default()
+        });
+        Ok(ActionResult::AlreadyApplied)
+    }
+    pub fn set(
+        &self,
+        key: String,
+        value: String,
+        if_absent: bool,
+    ) -> Result<ActionResult, ApplyError> {
+        let mut vars = load(&self.path)?;
+        if vars.get(&key) == Some(&value) || (if_absent && vars.contains_key(&key)) {
+            return Ok(ActionResult::AlreadyApplied);
+        }
+        vars.insert(key, value);
+        self.write(&vars)?;
+        Ok(ActionResult::Applied)
+    }
+    pub fn rm(&self, key: &str) -> Result<ActionResult, ApplyError> {
+        let mut vars = load(&self.path)?;
+        if vars.remove(key).is_none() {
+            return Ok(ActionResult::AlreadyUnapplied);
+        }
+        self.write(&vars)?;
+        Ok(ActionResult::Applied)
+    }
+    // $VISUAL or $EDITOR on a plaintext copy in a private temp dir, removed afterwards
+    pub fn edit(&self) -> Result<ActionResult, ApplyError> {
+        let vars = load(&self.path)?;
+        let dir = std::env::temp_dir().join(format!("fastidious-{}", rand::random::<u32>()));
+        DirBuilder::new().mode(0o700).create(&dir)?;
+        let file = dir.join("vars.yaml");
+        let edited = edit_file(&file, &to_yaml(&vars)?);
+        fs::remove_dir_all(&dir)?;
+        let edited = varsfile::parse(&file, &edited?)?;
+        if edited == vars {
+            return Ok(ActionResult::AlreadyApplied);
+        }
+        self.write(&edited)?;
+        Ok(ActionResult::Applied)
+    }
+    // encrypted to a new file first so a failed encryption keeps the old store
+    fn write(&self, vars: &Vars) -> Result<(), ApplyError> {
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        let mut command = match (is_gpg(&self.path), self.passphrase) {
+            (true, true) => gpg(&["--symmetric"]),
+            (true, false) => gpg(&["--encrypt", "--default-recipient-self"]),
+            (false, true) => age(&["--encrypt", "--passphrase"]),
+            (false, false) => {
+                let mut age = age(&["--encrypt", "--identity"]);
+                age.arg(&settings().age_identity);
+                age
+            }
+        };
+        let mut child = command
+            .arg("--output")
+            .arg(&tmp)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| ApplyError::ExecError(format!("encrypt {:?} {:?}", self.path, e)))?;
+        child
+            .stdin
+            .take()
+            .expect("piped stdin")
+            .write_all(to_yaml(vars)?.as_bytes())?;
+        let status = child.wait()?;
+        if !status.success() {
+            let _ = fs::remove_file(&tmp);
+            return Err(ApplyError::ExecError(format!(
+                "encrypt {:?} exited with {}",
+                self.path, status
+            )));
+        }
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+// an empty store until the first save
+pub fn load(path: &Path) -> Result<Vars, ApplyError> {
+    if !path.exists() {
+        return Ok(Vars::new());
+    }
+    varsfile::parse(&path.with_extension(""), &decrypt(path)?)
+}
+pub fn decrypt(path: &Path) -> Result<String, ApplyError> {
+    let mut command = if is_gpg(path) {
+        gpg(&["--decrypt"])
+    } else if is_passphrase(path) {
+        age(&["--decrypt"])
+    } else {
+        let mut age = age(&["--decrypt", "--identity"]);
+        age.arg(&settings().age_identity);
+        age
+    };
+    let output = command
+        .arg(path)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| ApplyError::ExecError(format!("decrypt {:?} {:?}", path, e)))?;
+    if !output.status.success() {
+        return Err(ApplyError::ExecError(format!(
+            "decrypt {:?} exited with {}",
+            path, output.status
+        )));
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|e| ApplyError::Error(format!("decrypt {:?} {:?}", path, e)))
+}
+// .gpg and .asc files are gpg, anything else age
+pub fn is_gpg(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("gpg") | Some("asc")
+    )
+}
+// an age file encrypted with a passphrase has an scrypt stanza in its header
+fn is_passphrase(path: &Path) -> bool {
+    let mut header = [0u8; 256];
+    let n = fs::File::open(path)
+        .and_then(|mut f| f.read(&mut header))
+        .unwrap_or(0);
+    String::from_utf8_lossy(&header[..n]).contains("\n-> scrypt ")
+}
+fn gpg(args: &[&str]) -> Command {
+    let mut gpg = Command::new("gpg");
+    gpg.arg("--quiet").args(args);
+    gpg
+}
+fn age(args: &[&str]) -> Command {
+    let mut age = Command::new("age");
+    age.args(args);
+    age
+}
+// sorted so the store diffs well when edited
+fn to_yaml(vars: &Vars) -> Result<String, ApplyError> {
+    let sorted: BTreeMap<&String, &String> = vars.iter().collect();
+    Ok(serde_yaml::to_string(&sorted)?)
+}
+fn edit_file(file: &Path, contents: &str) -> Result<String, ApplyError> {
+    OpenOptions::new()
+        .mode(0o600)
+        .write(true)
+        .create_new(true)
+        .open(file)?
+        .write_all(contents.as_bytes())?;
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from("vi"));
+    // the editor may have arguments, e.g. "code --wait"
+    let status = Command::new(&settings().shell)
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("editor")
+        .arg(file)
+        .status()
+        .map_err(|e| ApplyError::ExecError(format!("{} {:?}", editor, e)))?;
+    if !status.success() {
+        return Err(ApplyError::ExecError(format!(
+            "{} exited with {}",
+            editor, status
+        )));
+    }
+    Ok(fs::read_to_string(file)?)
+}
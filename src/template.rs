@@ -1,164 +1,632 @@
+use ansi_term::Colour::Yellow;
 use applyerr::ApplyError;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use files::{GenFile, SrcFile};
 use log::trace;
-use regex::Match;
-use regex::Regex;
-use std::collections::HashMap;
-use std::fs::File;
+use passive::{self, Event, Verb};
 use std::io::prelude::*;
-use std::io::BufReader;
-use std::io::Error;
 use std::ops::Range;
-use std::usize;
+use std::sync::OnceLock;
 
 use crate::cmd::Vars;
+use crate::resolver;
 
 #[test]
-fn test_match_line() {
-    fn extract_range<'a>(s: &'a str, r: Range<usize>) -> &'a str {
-        &s[r.start..r.end]
+fn test_scan() {
+    fn first_var(s: &str) -> (Range<usize>, Range<usize>) {
+        match scan(s, 0, &Delims::default())
+            .into_iter()
+            .find(|t| matches!(t, Token::Var { .. }))
+        {
+            Some(Token::Var { inner, outer, .. }) => (inner, outer),
+            _ => panic!("expected Template"),
+        }
     }
     let s1 = "a@@foo@@";
     let s2 = "@@foo@@a";
     let s3 = "@@foo@@";
-    match match_line(s1) {
-        Some((r, _outer)) => {
-            assert_eq!(r.start, 3);
-            assert_eq!(r.end, 6);
-            assert_eq!(extract_range(&s1, r), "foo");
+    let (r, _outer) = first_var(s1);
+    assert_eq!(r.start, 3);
+    assert_eq!(r.end, 6);
+    assert_eq!(&s1[r], "foo");
+    let (r, outer) = first_var(s2);
+    assert_eq!(r.start, 2);
+    assert_eq!(r.end, 5);
+    assert_eq!(&s2[r], "foo");
+    assert_eq!(&s2[outer], "@@foo@@");
+    assert_eq!(&s3[first_var(s3).0], "foo");
+    // the closing delimiter must be on the same line
+    assert_eq!(
+        scan("a@@b\nc@@d", 0, &Delims::default()),
+        vec![Token::Text("a@@b\nc@@d")]
+    );
+}
+#[test]
+fn test_render() -> Result<(), ApplyError> {
+    let mut vars = Vars::new();
+    vars.insert("user".into(), "sean".into());
+    vars.insert("group".into(), "staff".into());
+    vars.insert("motd".into(), "line 1\nline 2".into());
+    assert_eq!(render(&vars, "@@user@@:@@group@@")?, "sean:staff");
+    assert_eq!(render(&vars, "@@@@user@@@@")?, "@@user@@");
+    assert_eq!(render(&vars, "x\n@@motd@@\ny")?, "x\nline 1\nline 2\ny");
+    assert_eq!(render(&vars, "a @@ b")?, "a @@ b");
+    assert!(render(&vars, "@@ @@").is_err());
+    let braces = Delims::new("{{", "}}")?;
+    assert_eq!(
+        render_from(&vars, "@@ {{user}} {{}}", 0, &braces, Undefined::Error)?,
+        "@@ sean {{"
+    );
+    let text = "@@delims {{ }}@@\n@@ {{group}}\n";
+    assert_eq!(render(&vars, text)?, "@@ staff\n");
+    assert!(Delims::new("", "}}").is_err());
+    let at = Delims::default();
+    let text = "@@user@@ @@home | upper@@";
+    assert_eq!(
+        render_from(&vars, text, 0, &at, Undefined::Keep)?,
+        "sean @@home | upper@@"
+    );
+    assert_eq!(render_from(&vars, text, 0, &at, Undefined::Empty)?, "sean ");
+    let text = "@@if not debug@@@@x|1@@@@endif@@\n@@for h in hosts@@@@h@@ @@user@@@@endfor@@";
+    assert_eq!(list_vars(text)?, vec!["debug", "x", "hosts", "user"]);
+    match render(&vars, "@@user@@ @@home@@\n  @@shell@@") {
+        Err(ApplyError::VarNotFound(missing)) => {
+            assert_eq!(missing, "home at 1:10, shell at 2:3")
         }
-        None => panic!("expected Template"),
-    }
-    match match_line(s2) {
-        Some((r, outer)) => {
-            assert_eq!(r.start, 2);
-            assert_eq!(r.end, 5);
-            assert_eq!(extract_range(s2, r), "foo");
-            assert_eq!(extract_range(s2, outer), "@@foo@@");
+        other => panic!("expected VarNotFound {:?}", other),
+    }
+    Ok(())
+}
+#[test]
+fn test_blocks() -> Result<(), ApplyError> {
+    let mut vars = Vars::new();
+    vars.insert("name".into(), "it's".into());
+    vars.insert("debug".into(), "yes".into());
+    vars.insert("hosts".into(), "a, b".into());
+    assert_eq!(render(&vars, "@@port|8080@@ @@name|upper@@")?, "8080 IT'S");
+    assert_eq!(render(&vars, "@@name|shell_escape@@")?, "'it'\\''s'");
+    assert_eq!(
+        render(&vars, "@@name|json@@ @@x|\"a b\"|base64@@")?,
+        "\"it's\" YSBi"
+    );
+    let text = "@@if debug@@\nlog=debug\n@@else@@\nlog=info\n@@endif@@\n";
+    assert_eq!(render(&vars, text)?, "log=debug\n");
+    assert_eq!(render(&vars, "@@if not debug@@x@@endif@@")?, "");
+    let text = "@@for host in hosts@@\nserver @@host@@\n@@endfor@@\n";
+    assert_eq!(render(&vars, text)?, "server a\nserver b\n");
+    assert!(matches!(
+        render(&vars, "@@if debug@@\n"),
+        Err(ApplyError::TemplateError(_))
+    ));
+    assert!(matches!(
+        render(&vars, "@@endfor@@"),
+        Err(ApplyError::TemplateError(_))
+    ));
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Text(&'a str),
+    // inner is the key, outer includes the delimiters, line and col are 1-based
+    Var {
+        key: &'a str,
+        tag: &'a str,
+        inner: Range<usize>,
+        outer: Range<usize>,
+        line: usize,
+        col: usize,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Delims {
+    left: String,
+    right: String,
+}
+impl Default for Delims {
+    fn default() -> Self {
+        Self {
+            left: String::from("@@"),
+            right: String::from("@@"),
         }
-        None => panic!("expected Template"),
     }
-    match match_line(s3) {
-        Some((r, _outer)) => {
-            assert_eq!(extract_range(s3, r), "foo");
+}
+impl Delims {
+    pub fn new(left: &str, right: &str) -> Result<Self, ApplyError> {
+        if left.is_empty() || right.is_empty() || left.contains(char::is_whitespace) {
+            return Err(ApplyError::TemplateError(format!(
+                "bad delimiters {:?} {:?}",
+                left, right
+            )));
         }
-        None => panic!("expected Template"),
-    }
-}
-fn match_line(line: &str) -> Option<(Range<usize>, Range<usize>)> {
-    let left_delim = "@@";
-    let left_delim_len = left_delim.len();
-    let right_delim = "@@";
-    let right_delim_len = right_delim.len();
-    line.find(left_delim).and_then(|start_of_left_delim| {
-        line[start_of_left_delim + left_delim_len..]
-            .find(right_delim)
-            .map(|start_of_right_delim| {
-                (
-                    Range {
-                        start: start_of_left_delim + left_delim_len,
-                        end: start_of_right_delim + start_of_left_delim + left_delim_len,
-                    },
-                    Range {
-                        start: start_of_left_delim,
-                        end: (start_of_right_delim
-                            + start_of_left_delim
-                            + right_delim_len
-                            + left_delim_len),
-                    },
-                )
-            })
-    })
-}
-pub enum ChangeString {
-    Changed(String),
-    Unchanged,
-}
-pub fn replace_line2(vars: &Vars, line: &str) -> Result<String, ApplyError> {
-    match match_line(line) {
-        Some((inner, outer)) => {
-            debug!("{}", line,);
-            let key = &line[inner.start..inner.end];
-            trace!("key {}", key);
-            let mut new_line: String = String::new();
-            let v = vars.get(key);
-            trace!("vars {:?}", vars);
-            trace!("val {:?}", v);
-            trace!("line {:?}", line);
-            trace!("inner {} {}", inner.start, inner.end);
-            trace!("outer {} {}", outer.start, outer.end);
-            let before: &str = &line[..outer.start];
-            let after: &str = &line[outer.end..];
-            trace!("before {}", before);
-            new_line.push_str(before);
-
-            if let Some(value) = v {
-                new_line.push_str(value);
-                new_line.push_str(after);
-                new_line.push('\n');
-                trace!("value {}", value);
-                trace!("after {}", after);
-                Ok(new_line)
-            } else {
-                Err(ApplyError::VarNotFound(String::from(key)))
+        Ok(Self {
+            left: left.to_owned(),
+            right: right.to_owned(),
+        })
+    }
+}
+
+static DELIMS: OnceLock<Delims> = OnceLock::new();
+
+// what a placeholder for a var that is not set becomes
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum Undefined {
+    /// fail, listing every missing var
+    #[default]
+    Error,
+    /// an empty string
+    Empty,
+    /// the placeholder as written
+    Keep,
+    /// an empty string and a warning
+    Warn,
+}
+
+static UNDEFINED: OnceLock<Undefined> = OnceLock::new();
+
+pub fn set_undefined(undefined: Undefined) {
+    let _ = UNDEFINED.set(undefined);
+}
+
+// the delimiters for templates and args, @@ @@ unless set by --delims
+pub fn set_delims(delims: Delims) {
+    let _ = DELIMS.set(delims);
+}
+
+// a first line "@@delims {{ }}@@" switches the rest of the template to {{ }}
+fn header(text: &str, delims: &Delims) -> Result<Option<(Delims, usize)>, ApplyError> {
+    let line_end = text.find('\n').map_or(text.len(), |i| i + 1);
+    let tag = text[..line_end]
+        .trim_end()
+        .strip_prefix(delims.left.as_str())
+        .and_then(|tag| tag.strip_suffix(delims.right.as_str()));
+    match tag.map(|tag| tag.split_whitespace().collect::<Vec<&str>>()) {
+        Some(words) if words.first() == Some(&"delims") => match words[1..] {
+            [left, right] => Ok(Some((Delims::new(left, right)?, line_end))),
+            _ => Err(ApplyError::TemplateError(String::from(
+                "expected delims <left> <right> at 1:1",
+            ))),
+        },
+        _ => Ok(None),
+    }
+}
+
+// placeholders from `from` on, any number per line; left and right with nothing
+// in between is a literal left delimiter
+fn scan<'a>(text: &'a str, from: usize, delims: &'a Delims) -> Vec<Token<'a>> {
+    let left_delim = delims.left.as_str();
+    let right_delim = delims.right.as_str();
+    let mut tokens = Vec::new();
+    // start of text not yet in a token, and where to look for the next delimiter
+    let mut pos = from;
+    let mut search = from;
+    // line number at line_start, counted up to `counted`
+    let (mut line, mut line_start, mut counted) = (1, 0, 0);
+    while let Some(found) = text[search..].find(left_delim) {
+        let start = search + found;
+        let key_start = start + left_delim.len();
+        let line_end = text[key_start..]
+            .find('\n')
+            .map_or(text.len(), |i| key_start + i);
+        let key_len = match text[key_start..line_end].find(right_delim) {
+            Some(key_len) => key_len,
+            None => {
+                search = key_start;
+                continue;
             }
+        };
+        let key_end = key_start + key_len;
+        let end = key_end + right_delim.len();
+        let key = text[key_start..key_end].trim();
+        // a line holding just a block tag is left out of the output
+        let (mut text_end, mut next) = (start, end);
+        if is_statement(key) {
+            let tag_line_start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+            let tag_line_end = text[end..].find('\n').map_or(text.len(), |i| end + i + 1);
+            if tag_line_start >= pos
+                && text[tag_line_start..start].trim().is_empty()
+                && text[end..tag_line_end].trim().is_empty()
+            {
+                text_end = tag_line_start;
+                next = tag_line_end;
+            }
+        }
+        if pos < text_end {
+            tokens.push(Token::Text(&text[pos..text_end]));
         }
-        None => Ok(line.to_owned()),
-    }
-}
-pub fn replace_line(vars: &Vars, line: &str) -> Result<ChangeString, ApplyError> {
-    match match_line(line) {
-        Some((inner, outer)) => {
-            debug!("{}", line,);
-            let key = &line[inner.start..inner.end];
-            trace!("key {}", key);
-            let mut new_line: String = String::new();
-            let v = vars.get(key);
-            trace!("vars {:?}", vars);
-            trace!("val {:?}", v);
-            trace!("line {:?}", line);
-            trace!("inner {} {}", inner.start, inner.end);
-            trace!("outer {} {}", outer.start, outer.end);
-            let before: &str = &line[..outer.start];
-            let after: &str = &line[outer.end..];
-            trace!("before {}", before);
-            new_line.push_str(before);
-
-            if let Some(value) = v {
-                new_line.push_str(value);
-                new_line.push_str(after);
-                new_line.push('\n');
-                trace!("value {}", value);
-                trace!("after {}", after);
-                Ok(ChangeString::Changed(new_line))
-            } else {
-                Err(ApplyError::VarNotFound(String::from(key)))
+        if key_len == 0 {
+            tokens.push(Token::Text(left_delim));
+        } else {
+            for (i, _) in text[counted..start].match_indices('\n') {
+                line += 1;
+                line_start = counted + i + 1;
             }
+            counted = start;
+            tokens.push(Token::Var {
+                key,
+                tag: &text[start..end],
+                inner: key_start..key_end,
+                outer: start..end,
+                line,
+                col: text[line_start..start].chars().count() + 1,
+            });
         }
-        None => Ok(ChangeString::Unchanged),
+        pos = next;
+        search = next;
+    }
+    if pos < text.len() {
+        tokens.push(Token::Text(&text[pos..]));
     }
+    tokens
 }
-// creates the tmp file for comparing to the dest file
-pub fn generate_recommended_file(vars: Vars, template: &SrcFile) -> Result<GenFile, ApplyError> {
-    let gen = GenFile::new()?;
-    let maybe_infile = template.open();
-    let infile = maybe_infile?;
-    let reader = BufReader::new(infile.file());
-    let mut tmpfile = gen.open()?;
-    for maybe_line in reader.lines() {
-        let line: String = maybe_line.unwrap();
-        match replace_line(&vars, &line) {
-            Ok(replaced_line) => match replaced_line {
-                ChangeString::Changed(new_line) => {
-                    writeln!(tmpfile, "{}", new_line).expect("write failed");
+
+// words that open or close a block, the rest of a tag is a var expression
+const STATEMENTS: [&str; 5] = ["if", "else", "endif", "for", "endfor"];
+const FILTERS: [&str; 6] = ["upper", "lower", "quote", "shell_escape", "json", "base64"];
+
+fn is_statement(tag: &str) -> bool {
+    tag.split_whitespace()
+        .next()
+        .is_some_and(|word| STATEMENTS.contains(&word))
+}
+
+#[derive(Debug)]
+enum Node<'a> {
+    Text(&'a str),
+    // key|default|filter..., tag is the placeholder as written
+    Expr {
+        expr: &'a str,
+        tag: &'a str,
+        line: usize,
+        col: usize,
+    },
+    If {
+        cond: &'a str,
+        then: Vec<Node<'a>>,
+        otherwise: Vec<Node<'a>>,
+    },
+    For {
+        name: &'a str,
+        list: &'a str,
+        line: usize,
+        col: usize,
+        body: Vec<Node<'a>>,
+    },
+}
+
+// a closing tag: else, endif or endfor with its position
+type Closing<'a> = Option<(&'a str, usize, usize)>;
+
+fn parse<'a>(text: &'a str, from: usize, delims: &'a Delims) -> Result<Vec<Node<'a>>, ApplyError> {
+    let mut tokens = scan(text, from, delims).into_iter();
+    match parse_block(&mut tokens)? {
+        (nodes, None) => Ok(nodes),
+        (_, Some((word, line, col))) => Err(ApplyError::TemplateError(format!(
+            "unexpected {} at {}:{}",
+            word, line, col
+        ))),
+    }
+}
+// nodes up to the next closing tag or the end of the template
+fn parse_block<'a, I: Iterator<Item = Token<'a>>>(
+    tokens: &mut I,
+) -> Result<(Vec<Node<'a>>, Closing<'a>), ApplyError> {
+    let mut nodes = Vec::new();
+    while let Some(token) = tokens.next() {
+        let (key, tag, line, col) = match token {
+            Token::Text(s) => {
+                nodes.push(Node::Text(s));
+                continue;
+            }
+            Token::Var {
+                key,
+                tag,
+                line,
+                col,
+                ..
+            } => (key, tag, line, col),
+        };
+        let words: Vec<&str> = key.split_whitespace().collect();
+        match words.first().copied().unwrap_or("") {
+            "if" => {
+                let cond = key["if".len()..].trim();
+                let (then, end) = parse_block(tokens)?;
+                let otherwise = match end {
+                    Some(("else", _, _)) => {
+                        let (otherwise, end) = parse_block(tokens)?;
+                        expect_end(end, "endif", "if", line, col)?;
+                        otherwise
+                    }
+                    end => {
+                        expect_end(end, "endif", "if", line, col)?;
+                        Vec::new()
+                    }
+                };
+                nodes.push(Node::If {
+                    cond,
+                    then,
+                    otherwise,
+                });
+            }
+            "for" => {
+                if words.len() != 4 || words[2] != "in" {
+                    return Err(ApplyError::TemplateError(format!(
+                        "expected for <name> in <list> at {}:{}",
+                        line, col
+                    )));
+                }
+                let (body, end) = parse_block(tokens)?;
+                expect_end(end, "endfor", "for", line, col)?;
+                nodes.push(Node::For {
+                    name: words[1],
+                    list: words[3],
+                    line,
+                    col,
+                    body,
+                });
+            }
+            word @ ("else" | "endif" | "endfor") => return Ok((nodes, Some((word, line, col)))),
+            _ => nodes.push(Node::Expr {
+                expr: key,
+                tag,
+                line,
+                col,
+            }),
+        }
+    }
+    Ok((nodes, None))
+}
+fn expect_end(
+    end: Closing,
+    word: &str,
+    open: &str,
+    line: usize,
+    col: usize,
+) -> Result<(), ApplyError> {
+    match end {
+        Some((found, _, _)) if found == word => Ok(()),
+        Some((found, l, c)) => Err(ApplyError::TemplateError(format!(
+            "unexpected {} at {}:{}, {} at {}:{} needs {}",
+            found, l, c, open, line, col, word
+        ))),
+        None => Err(ApplyError::TemplateError(format!(
+            "{} at {}:{} needs {}",
+            open, line, col, word
+        ))),
+    }
+}
+
+// fills every placeholder, values may span lines; all missing vars are reported at once
+pub fn render(vars: &Vars, text: &str) -> Result<String, ApplyError> {
+    let (delims, from) = delims_for(text)?;
+    let undefined = UNDEFINED.get().cloned().unwrap_or_default();
+    render_from(vars, text, from, &delims, undefined)
+}
+// --delims or the default, unless the template has a delims header
+fn delims_for(text: &str) -> Result<(Delims, usize), ApplyError> {
+    let delims = DELIMS.get().cloned().unwrap_or_default();
+    Ok(header(text, &delims)?.unwrap_or((delims, 0)))
+}
+fn render_from(
+    vars: &Vars,
+    text: &str,
+    from: usize,
+    delims: &Delims,
+    undefined: Undefined,
+) -> Result<String, ApplyError> {
+    let nodes = parse(text, from, delims)?;
+    // only the references this template reads are looked up
+    let mut names = Vec::new();
+    collect_vars(&nodes, &[], &mut names);
+    let vars = &resolver::resolve_vars(vars, &names)?;
+    let mut out = String::with_capacity(text.len());
+    let mut missing: Vec<String> = Vec::new();
+    render_nodes(&nodes, vars, undefined, &mut out, &mut missing);
+    match undefined {
+        Undefined::Error if !missing.is_empty() => Err(ApplyError::VarNotFound(missing.join(", "))),
+        Undefined::Warn => {
+            for at in missing {
+                warn_undefined(at);
+            }
+            Ok(out)
+        }
+        _ => Ok(out),
+    }
+}
+// stderr, the template may be rendered to stdout
+fn warn_undefined(at: String) {
+    if passive::is_text() {
+        let line = format!("{} {}", Yellow.paint("WARN: undefined"), at);
+        if passive::use_color() {
+            eprintln!("{}", line);
+        } else {
+            eprintln!("{}", passive::strip_color(&line));
+        }
+    }
+    passive::emit(Event {
+        verb: Some(Verb::Skipped),
+        action: String::from("undefined var"),
+        error: Some(at),
+        ..Event::default()
+    });
+}
+fn render_nodes(
+    nodes: &[Node],
+    vars: &Vars,
+    undefined: Undefined,
+    out: &mut String,
+    missing: &mut Vec<String>,
+) {
+    for node in nodes {
+        match node {
+            Node::Text(s) => out.push_str(s),
+            Node::Expr {
+                expr,
+                tag,
+                line,
+                col,
+            } => match eval(expr, vars) {
+                Some(value) => {
+                    trace!("{} = {:?}", expr, value);
+                    out.push_str(&value);
                 }
-                ChangeString::Unchanged => {
-                    trace!("no vars in line {:?}", line);
-                    writeln!(tmpfile, "{}", line).expect("Cannot write to tmp file");
+                None => {
+                    if undefined == Undefined::Keep {
+                        out.push_str(tag);
+                    }
+                    not_found(missing, key_of(expr), *line, *col)
+                }
+            },
+            Node::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                let branch = if truthy(vars, cond) { then } else { otherwise };
+                render_nodes(branch, vars, undefined, out, missing);
+            }
+            Node::For {
+                name,
+                list,
+                line,
+                col,
+                body,
+            } => match vars.get(*list) {
+                Some(items) => {
+                    for item in split_list(items) {
+                        let mut scope = vars.clone();
+                        scope.insert(name.to_string(), item.to_owned());
+                        render_nodes(body, &scope, undefined, out, missing);
+                    }
                 }
+                None => not_found(missing, list, *line, *col),
             },
-            Err(e) => return Err(e),
         }
     }
+}
+// every var a template reads in order of first use, without loop variables
+pub fn list_vars(text: &str) -> Result<Vec<String>, ApplyError> {
+    let (delims, from) = delims_for(text)?;
+    let nodes = parse(text, from, &delims)?;
+    let mut names = Vec::new();
+    collect_vars(&nodes, &[], &mut names);
+    Ok(names)
+}
+fn collect_vars<'a>(nodes: &[Node<'a>], bound: &[&'a str], names: &mut Vec<String>) {
+    let add = |names: &mut Vec<String>, name: &str| {
+        if !name.is_empty() && !bound.contains(&name) && !names.iter().any(|n| n == name) {
+            names.push(name.to_owned());
+        }
+    };
+    for node in nodes {
+        match node {
+            Node::Text(_) => {}
+            Node::Expr { expr, .. } => add(names, key_of(expr)),
+            Node::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                add(names, cond.strip_prefix("not ").unwrap_or(cond).trim());
+                collect_vars(then, bound, names);
+                collect_vars(otherwise, bound, names);
+            }
+            Node::For {
+                name, list, body, ..
+            } => {
+                add(names, list);
+                let mut inner = bound.to_vec();
+                inner.push(name);
+                collect_vars(body, &inner, names);
+            }
+        }
+    }
+}
+fn key_of(expr: &str) -> &str {
+    expr.split('|').next().unwrap_or("").trim()
+}
+// once per placeholder, a loop body is rendered many times
+fn not_found(missing: &mut Vec<String>, key: &str, line: usize, col: usize) {
+    let at = format!("{} at {}:{}", key, line, col);
+    if !missing.contains(&at) {
+        missing.push(at);
+    }
+}
+// after the key, a segment that is not a filter name is the default
+fn eval(expr: &str, vars: &Vars) -> Option<String> {
+    let mut segments = expr.split('|').map(str::trim);
+    let mut value = segments.next().and_then(|key| vars.get(key).cloned());
+    for segment in segments {
+        value = match value {
+            Some(v) if FILTERS.contains(&segment) => Some(filter(segment, &v)),
+            None if !FILTERS.contains(&segment) => Some(unquote(segment).to_owned()),
+            v => v,
+        };
+    }
+    value
+}
+fn filter(name: &str, value: &str) -> String {
+    match name {
+        "upper" => value.to_uppercase(),
+        "lower" => value.to_lowercase(),
+        "quote" => format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+        "shell_escape" => shell_escape(value),
+        "json" => serde_json::to_string(value).expect("string to json"),
+        "base64" => STANDARD.encode(value),
+        _ => value.to_owned(),
+    }
+}
+// single quoted unless every char is safe, like python's shlex.quote
+fn shell_escape(value: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "@%+=:,./-_".contains(c);
+    if !value.is_empty() && value.chars().all(safe) {
+        value.to_owned()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+// a default may be quoted to keep spaces or a filter name
+fn unquote(s: &str) -> &str {
+    if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+        &s[1..s.len() - 1]
+    } else {
+        s
+    }
+}
+// set and not empty, false, 0 or no; "not key" negates
+fn truthy(vars: &Vars, cond: &str) -> bool {
+    match cond.strip_prefix("not ") {
+        Some(key) => !truthy(vars, key.trim()),
+        None => vars
+            .get(cond)
+            .is_some_and(|v| !matches!(v.trim(), "" | "false" | "0" | "no")),
+    }
+}
+// list values are separated by commas or whitespace
+fn split_list(value: &str) -> Vec<&str> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+pub fn read_template(template: &SrcFile) -> Result<String, ApplyError> {
+    let infile = template.open()?;
+    let mut text = String::new();
+    let mut reader = infile.file();
+    reader.read_to_string(&mut text)?;
+    Ok(text)
+}
+// creates the tmp file for comparing to the dest file
+pub fn generate_recommended_file(vars: Vars, template: &SrcFile) -> Result<GenFile, ApplyError> {
+    let text = read_template(template)?;
+    let mut generated = render(&vars, &text)?;
+    // as before, every line ends with a newline
+    if !generated.is_empty() && !generated.ends_with('\n') {
+        generated.push('\n');
+    }
+    let gen = GenFile::new()?;
+    gen.open()?.write_all(generated.as_bytes())?;
     Ok(gen)
 }
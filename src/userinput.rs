@@ -1,8 +1,16 @@
+use passive;
+use secret;
 use std::io::stdin;
 
 pub fn ask(question: &str) -> char {
+    let question = secret::redact(question);
     loop {
-        println!("{}", question);
+        // keep stdout clean for --format json
+        if passive::is_text() {
+            println!("{}", question);
+        } else {
+            eprintln!("{}", question);
+        }
         let mut line = String::new();
         stdin().read_line(&mut line).expect("No User Input");
         if !line.is_empty() {
@@ -0,0 +1,156 @@
+use applyerr::ApplyError;
+use cmd::Vars;
+use config::{Config, FileFormat, Value, ValueKind};
+use secret;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use store;
+
+#[test]
+fn test_load_all() -> Result<(), ApplyError> {
+    let dir = std::env::temp_dir();
+    let yaml = dir.join("test_vars.yaml");
+    let toml = dir.join("test_vars.toml");
+    let json = dir.join("test_vars.json");
+    let dotenv = dir.join("test_vars.env");
+    std::fs::write(&yaml, "user: sean\ndb:\n  host: localhost\n  port: 5432\n")?;
+    std::fs::write(&toml, "user = \"root\"\nhosts = [\"a\", \"b\"]\n")?;
+    std::fs::write(&json, "{\"db\": {\"port\": 5433}, \"debug\": true}")?;
+    std::fs::write(
+        &dotenv,
+        "# comment\nexport TOKEN=\"a b\\n\\\\n\"\nEMPTY=\nPLAIN=x # note\n",
+    )?;
+    let vars = load_all(&[yaml.clone(), toml.clone(), json.clone(), dotenv.clone()])?;
+    assert_eq!(vars.get("user").unwrap(), "root");
+    assert_eq!(vars.get("db.host").unwrap(), "localhost");
+    assert_eq!(vars.get("db.port").unwrap(), "5433");
+    assert_eq!(vars.get("hosts").unwrap(), "a,b");
+    assert_eq!(vars.get("debug").unwrap(), "true");
+    assert_eq!(vars.get("TOKEN").unwrap(), "a b\n\\n");
+    assert_eq!(vars.get("EMPTY").unwrap(), "");
+    assert_eq!(vars.get("PLAIN").unwrap(), "x");
+    for path in [yaml, toml, json, dotenv] {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+// later files override earlier ones
+pub fn load_all(paths: &[PathBuf]) -> Result<Vars, ApplyError> {
+    let mut vars = Vars::new();
+    for path in paths {
+        vars.extend(load(path)?);
+    }
+    Ok(vars)
+}
+
+pub fn load(path: &Path) -> Result<Vars, ApplyError> {
+    debug!("vars file {:?}", path);
+    // every value of an encrypted file is a secret
+    if matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("age") | Some("gpg") | Some("asc")
+    ) {
+        let vars = parse(&path.with_extension(""), &store::decrypt(path)?)?;
+        for value in vars.values() {
+            secret::add(value);
+        }
+        return Ok(vars);
+    }
+    parse(path, &std::fs::read_to_string(path)?)
+}
+// by the extension of path; .env files are dotenv and anything else is yaml, as written by save
+pub fn parse(path: &Path, contents: &str) -> Result<Vars, ApplyError> {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string());
+    let format = match path.extension().and_then(|e| e.to_str()) {
+        Some("env") => return parse_dotenv(contents),
+        _ if name.is_some_and(|n| n.starts_with(".env")) => return parse_dotenv(contents),
+        Some("toml") => FileFormat::Toml,
+        Some("json") => FileFormat::Json,
+        _ => FileFormat::Yaml,
+    };
+    let conf = Config::builder()
+        .add_source(config::File::from_str(contents, format))
+        .build()?;
+    let mut vars = Vars::new();
+    for (key, value) in conf.try_deserialize::<HashMap<String, Value>>()? {
+        flatten(key, value, &mut vars)?;
+    }
+    Ok(vars)
+}
+
+// nested keys are joined with dots, arrays with commas for @@for@@
+pub(crate) fn flatten(key: String, value: Value, vars: &mut Vars) -> Result<(), ApplyError> {
+    match value.kind {
+        ValueKind::Table(_) => {
+            for (child, value) in value.into_table()? {
+                flatten(format!("{}.{}", key, child), value, vars)?;
+            }
+        }
+        ValueKind::Array(_) => {
+            let items = value
+                .into_array()?
+                .into_iter()
+                .map(|item| item.into_string())
+                .collect::<Result<Vec<String>, _>>()?;
+            vars.insert(key, items.join(","));
+        }
+        ValueKind::Nil => {
+            vars.insert(key, String::new());
+        }
+        _ => {
+            vars.insert(key, secret::reveal(value.into_string()?));
+        }
+    }
+    Ok(())
+}
+
+// KEY=value lines with optional export, # comments and quoted values
+fn parse_dotenv(contents: &str) -> Result<Vars, ApplyError> {
+    let mut vars = Vars::new();
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        match line.split_once('=') {
+            Some((key, value)) => {
+                let value = secret::reveal(dotenv_value(value.trim()));
+                vars.insert(key.trim().to_owned(), value);
+            }
+            None => {
+                return Err(ApplyError::Error(format!(
+                    "expected KEY=value at line {}",
+                    n + 1
+                )))
+            }
+        }
+    }
+    Ok(vars)
+}
+fn dotenv_value(value: &str) -> String {
+    let quoted = |q: char| value.len() >= 2 && value.starts_with(q) && value.ends_with(q);
+    if quoted('\'') {
+        value[1..value.len() - 1].to_owned()
+    } else if quoted('"') {
+        let mut unescaped = String::new();
+        let mut chars = value[1..value.len() - 1].chars();
+        while let Some(c) = chars.next() {
+            match (c, chars.clone().next()) {
+                ('\\', Some(next)) => {
+                    chars.next();
+                    unescaped.push(if next == 'n' { '\n' } else { next });
+                }
+                _ => unescaped.push(c),
+            }
+        }
+        unescaped
+    } else {
+        value
+            .split_once(" #")
+            .map_or(value, |(v, _)| v)
+            .trim_end()
+            .to_owned()
+    }
+}
@@ -0,0 +1,316 @@
+use applyerr::ApplyError;
+use serde_yaml::{Mapping, Value};
+
+#[test]
+fn test_save_yaml() -> Result<(), ApplyError> {
+    let text =
+        "# settings\nuser: sean # owner\ndb:\n  host: localhost\n  port: 5432\n\n# last\nlast: x\n";
+    let set = |text: &str, key: &str, value: &str| {
+        save_yaml(text, key, &Change::Set(parse_value(value, false)?))
+    };
+    assert_eq!(
+        set(text, "db.port", "5433")?.unwrap(),
+        text.replace("5432", "5433")
+    );
+    assert_eq!(
+        set(text, "user", "root")?.unwrap(),
+        text.replace("sean", "root")
+    );
+    assert_eq!(set(text, "db.port", "5432")?, None);
+    assert_eq!(
+        set(text, "db.pool.size", "[1, 2]")?.unwrap(),
+        text.replace("5432\n", "5432\n  pool:\n    size:\n      - 1\n      - 2\n")
+    );
+    assert_eq!(
+        save_yaml(text, "user", &Change::SetIfAbsent(Value::from("root")))?,
+        None
+    );
+    assert_eq!(
+        save_yaml(text, "db", &Change::Delete)?.unwrap(),
+        "# settings\nuser: sean # owner\n\n# last\nlast: x\n"
+    );
+    assert_eq!(save_yaml(text, "nosuchkey", &Change::Delete)?, None);
+    assert_eq!(
+        save_yaml("", "a.b", &Change::Set(Value::from("c")))?.unwrap(),
+        "a:\n  b: c\n"
+    );
+    // flow style is rewritten
+    assert_eq!(
+        set("db: {port: 1}\n", "db.port", "2")?.unwrap(),
+        "db:\n  port: 2\n"
+    );
+    assert!(set(text, "user.name", "x").is_err());
+    Ok(())
+}
+
+// what save does to a key
+#[derive(Debug)]
+pub enum Change {
+    Set(Value),
+    SetIfAbsent(Value),
+    Delete,
+}
+
+// yaml unless it has to stay a string: 5432 is a number, [a, b] a list
+pub fn parse_value(value: &str, string: bool) -> Result<Value, ApplyError> {
+    if string || value.is_empty() {
+        return Ok(Value::from(value));
+    }
+    Ok(serde_yaml::from_str(value)?)
+}
+
+// the text with key (dotted for nested keys) changed, None when it already is.
+// Lines are edited in place so comments and ordering stay; when that does not give
+// the expected yaml, e.g. for flow style, the whole file is written again
+pub fn save_yaml(text: &str, key: &str, change: &Change) -> Result<Option<String>, ApplyError> {
+    let path: Vec<&str> = key.split('.').collect();
+    if path.iter().any(|k| k.is_empty()) {
+        return Err(ApplyError::ExpectedArg(format!("key, got {:?}", key)));
+    }
+    let mut expected: Value = serde_yaml::from_str(text)?;
+    if expected.is_null() {
+        expected = Value::Mapping(Mapping::new());
+    }
+    let old = lookup(&expected, &path).cloned();
+    match change {
+        Change::SetIfAbsent(_) if old.is_some() => return Ok(None),
+        Change::Set(value) | Change::SetIfAbsent(value) if old.as_ref() == Some(value) => {
+            return Ok(None)
+        }
+        Change::Set(value) | Change::SetIfAbsent(value) => {
+            insert(&mut expected, &path, value.clone())?
+        }
+        Change::Delete if old.is_none() => return Ok(None),
+        Change::Delete => remove(&mut expected, &path),
+    }
+    if let Some(edited) = edit_lines(text, &path, change)? {
+        if serde_yaml::from_str::<Value>(&edited).ok().as_ref() == Some(&expected) {
+            return Ok(Some(edited));
+        }
+    }
+    debug!("rewriting yaml for {}", key);
+    Ok(Some(serde_yaml::to_string(&expected)?))
+}
+
+fn lookup<'a>(value: &'a Value, path: &[&str]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |v, k| v.as_mapping()?.get(*k))
+}
+// missing or empty parents become mappings, any other value is an error
+fn insert(value: &mut Value, path: &[&str], new: Value) -> Result<(), ApplyError> {
+    let mut current = value;
+    for (i, key) in path.iter().enumerate() {
+        if current.is_null() {
+            *current = Value::Mapping(Mapping::new());
+        }
+        let map = current.as_mapping_mut().ok_or_else(|| {
+            ApplyError::Error(format!("{} is not a mapping", path[..i].join(".")))
+        })?;
+        if i == path.len() - 1 {
+            map.insert(Value::from(*key), new);
+            break;
+        }
+        current = map
+            .entry(Value::from(*key))
+            .or_insert(Value::Mapping(Mapping::new()));
+    }
+    Ok(())
+}
+fn remove(value: &mut Value, path: &[&str]) {
+    let (last, parents) = path.split_last().expect("a key");
+    if let Some(map) = parents
+        .iter()
+        .try_fold(value, |v, k| v.as_mapping_mut()?.get_mut(*k))
+        .and_then(Value::as_mapping_mut)
+    {
+        map.shift_remove(*last);
+    }
+}
+
+// block style mappings only, None when the lines can't be edited
+fn edit_lines(text: &str, path: &[&str], change: &Change) -> Result<Option<String>, ApplyError> {
+    let mut lines: Vec<String> = text.lines().map(String::from).collect();
+    let found = find_path(&lines, path);
+    match (change, found.len() == path.len()) {
+        (Change::Delete, true) => {
+            let i = found[found.len() - 1];
+            lines.drain(i..=last_child(&lines, i));
+        }
+        (Change::Set(value) | Change::SetIfAbsent(value), true) => {
+            let i = found[found.len() - 1];
+            let (prefix, rest) = split_key(&lines[i]).expect("a key line");
+            let (_, comment) = split_comment(rest);
+            let new = render(prefix, comment, indent(&lines[i]), value)?;
+            lines.splice(i..=last_child(&lines, i), new);
+        }
+        (Change::Set(value) | Change::SetIfAbsent(value), false) => {
+            let (at, mut child_indent) = match found.last() {
+                // a parent with a value on its line is not a block
+                Some(&p)
+                    if !split_comment(split_key(&lines[p]).expect("a key line").1)
+                        .0
+                        .is_empty() =>
+                {
+                    return Ok(None)
+                }
+                Some(&p) => {
+                    let end = last_child(&lines, p);
+                    let child_indent =
+                        block_indent(&lines, p + 1, end + 1).unwrap_or(indent(&lines[p]) + 2);
+                    (end + 1, child_indent)
+                }
+                None => (
+                    lines.len(),
+                    block_indent(&lines, 0, lines.len()).unwrap_or(0),
+                ),
+            };
+            let mut new = Vec::new();
+            let (last, parents) = path[found.len()..].split_last().expect("a key");
+            for key in parents {
+                new.push(format!("{}{}:", " ".repeat(child_indent), yaml_key(key)?));
+                child_indent += 2;
+            }
+            let prefix = format!("{}{}:", " ".repeat(child_indent), yaml_key(last)?);
+            new.extend(render(&prefix, "", child_indent, value)?);
+            lines.splice(at..at, new);
+        }
+        (Change::Delete, false) => return Ok(None),
+    }
+    let mut edited = lines.join("\n");
+    edited.push('\n');
+    Ok(Some(edited))
+}
+// the line of each key in path, as far as they are found
+fn find_path(lines: &[String], path: &[&str]) -> Vec<usize> {
+    let mut found = Vec::new();
+    let (mut start, mut end) = (0, lines.len());
+    for key in path {
+        let child_indent = match block_indent(lines, start, end) {
+            Some(n) => n,
+            None => break,
+        };
+        let line = (start..end).find(|&i| {
+            is_content(&lines[i])
+                && indent(&lines[i]) == child_indent
+                && split_key(&lines[i]).is_some_and(|(k, _)| key_name(k) == *key)
+        });
+        match line {
+            Some(i) => {
+                found.push(i);
+                start = i + 1;
+                end = last_child(lines, i) + 1;
+            }
+            None => break,
+        }
+    }
+    found
+}
+// the last line that is more indented than line i, or i
+fn last_child(lines: &[String], i: usize) -> usize {
+    let parent = indent(&lines[i]);
+    let mut last = i;
+    for (j, line) in lines.iter().enumerate().skip(i + 1) {
+        if is_content(line) {
+            if indent(line) <= parent {
+                break;
+            }
+            last = j;
+        }
+    }
+    last
+}
+fn block_indent(lines: &[String], start: usize, end: usize) -> Option<usize> {
+    lines[start..end]
+        .iter()
+        .filter(|l| is_content(l))
+        .map(|l| indent(l))
+        .min()
+}
+fn is_content(line: &str) -> bool {
+    let line = line.trim();
+    !line.is_empty() && !line.starts_with('#') && line != "---"
+}
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+// "  key: value # note" is ("  key:", " value # note")
+fn split_key(line: &str) -> Option<(&str, &str)> {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with("- ") || trimmed.starts_with('#') {
+        return None;
+    }
+    let start = line.len() - trimmed.len();
+    let colon = match trimmed.chars().next() {
+        Some(q @ '"') | Some(q @ '\'') => {
+            let close = trimmed[1..].find(q)? + 1;
+            trimmed[close..].starts_with(':').then_some(close)?
+        }
+        _ => trimmed
+            .find(": ")
+            .or_else(|| trimmed.strip_suffix(':').map(|k| k.len()))?,
+    };
+    Some(line.split_at(start + colon + 1))
+}
+// the value and its " # comment", outside of quotes
+fn split_comment(rest: &str) -> (&str, &str) {
+    let mut quote = None;
+    let mut prev = ' ';
+    for (i, c) in rest.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '#') if prev.is_whitespace() => {
+                let start = rest[..i].trim_end().len();
+                return (rest[..start].trim(), &rest[start..]);
+            }
+            _ => (),
+        }
+        prev = c;
+    }
+    (rest.trim(), "")
+}
+// "  'a b':" is a b
+fn key_name(prefix: &str) -> &str {
+    let key = prefix.trim().strip_suffix(':').unwrap_or(prefix);
+    key.strip_prefix('"')
+        .and_then(|k| k.strip_suffix('"'))
+        .or_else(|| key.strip_prefix('\'').and_then(|k| k.strip_suffix('\'')))
+        .unwrap_or(key)
+}
+fn yaml_key(key: &str) -> Result<String, ApplyError> {
+    Ok(serde_yaml::to_string(&Value::from(key))?
+        .trim_end()
+        .to_owned())
+}
+// "prefix value # comment", or a block below prefix for a non empty mapping or list
+fn render(
+    prefix: &str,
+    comment: &str,
+    indent: usize,
+    value: &Value,
+) -> Result<Vec<String>, ApplyError> {
+    let yaml = serde_yaml::to_string(value)?;
+    let mut yaml = yaml.trim_end().lines();
+    let nested = match value {
+        Value::Mapping(m) => !m.is_empty(),
+        Value::Sequence(s) => !s.is_empty(),
+        _ => false,
+    };
+    let mut lines = Vec::new();
+    let child = if nested {
+        lines.push(format!("{}{}", prefix, comment));
+        indent + 2
+    } else {
+        lines.push(format!(
+            "{} {}{}",
+            prefix,
+            yaml.next().unwrap_or(""),
+            comment
+        ));
+        // a multi-line string is already indented below its |
+        indent
+    };
+    for line in yaml {
+        lines.push(format!("{}{}", " ".repeat(child), line));
+    }
+    Ok(lines)
+}